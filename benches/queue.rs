@@ -0,0 +1,53 @@
+// 任务队列提交性能对比: 旧实现 (Vec + 每次插入后排序) vs 调度器的二叉堆
+// 运行: cargo bench --bench queue
+use std::time::{Duration, Instant};
+
+use task_flow_rs::{Executable, Priority, Scheduler, TaskContext, TaskError};
+
+// 什么都不做的任务, 只用来测量入队开销
+struct NoopTask;
+
+impl Executable for NoopTask {
+    fn execute(&self, _ctx: &TaskContext) -> Result<(), TaskError> {
+        Ok(())
+    }
+
+    fn get_name(&self) -> String {
+        "noop".to_string()
+    }
+}
+
+fn priority_of(i: usize) -> Priority {
+    match i % 3 {
+        0 => Priority::High,
+        1 => Priority::Medium,
+        _ => Priority::Low,
+    }
+}
+
+// 旧版 add_task 的做法: push 之后对整个 Vec 重新排序
+fn vec_sort_submit(n: usize) -> Duration {
+    let mut tasks: Vec<(Priority, Box<dyn Executable>)> = Vec::new();
+    let started = Instant::now();
+    for i in 0..n {
+        tasks.push((priority_of(i), Box::new(NoopTask)));
+        tasks.sort_by_key(|(priority, _)| *priority);
+    }
+    started.elapsed()
+}
+
+fn heap_submit(n: usize) -> Duration {
+    let scheduler = Scheduler::new();
+    let started = Instant::now();
+    for i in 0..n {
+        scheduler.add_task(priority_of(i), Box::new(NoopTask)).unwrap();
+    }
+    started.elapsed()
+}
+
+fn main() {
+    println!("{:>8} | {:>16} | {:>16}", "任务数", "Vec + sort_by", "BinaryHeap");
+    for n in [1_000, 5_000, 10_000, 20_000] {
+        println!("{:>8} | {:>16?} | {:>16?}", n, vec_sort_submit(n), heap_submit(n));
+    }
+}
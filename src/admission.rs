@@ -0,0 +1,105 @@
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use crate::{Executable, Priority, TaskError, TaskOptions};
+
+// 准入检查时看到的任务信息
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub name: String,
+    pub priority: Priority,         // 提交时的优先级
+    pub estimate: Option<Duration>, // TaskOptions::estimate 或任务自己声明的预估运行时间
+    pub tenant: String,
+}
+
+impl Submission {
+    pub(crate) fn new(priority: Priority, task: &dyn Executable, options: &TaskOptions) -> Self {
+        Submission {
+            name: task.get_name(),
+            priority,
+            estimate: options.estimate.or_else(|| task.estimated_duration()),
+            tenant: options.tenant_name().to_string(),
+        }
+    }
+}
+
+type Rule = Arc<dyn Fn(&Submission) -> bool + Send + Sync>;
+
+// 准入策略: 提交任务时检查, 不满足的任务返回 TaskError::Rejected, 不会进入队列
+// 排队数包括就绪, 延迟和等待上游的任务, 不包括正在运行的任务
+#[derive(Clone, Default)]
+pub struct AdmissionPolicy {
+    max_estimate: Option<Duration>,
+    max_queued: Option<usize>,
+    quotas: HashMap<Priority, usize>, // 命名级别 -> 这一级最多的排队任务数
+    rules: Vec<(String, Rule)>,
+}
+
+impl AdmissionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 拒绝预估运行时间超过 max 的任务, 没有预估的任务不受限制
+    pub fn max_estimate(mut self, max: Duration) -> Self {
+        self.max_estimate = Some(max);
+        self
+    }
+
+    // 排队任务数达到 max 后拒绝新任务
+    pub fn max_queued(mut self, max: usize) -> Self {
+        self.max_queued = Some(max);
+        self
+    }
+
+    // level 所在命名级别最多 max 个排队任务, 自定义的数值按不高于它的命名级别计算
+    pub fn quota(mut self, level: Priority, max: usize) -> Self {
+        self.quotas.insert(level.level(), max);
+        self
+    }
+
+    // 自定义规则, 返回 false 的任务被拒绝, name 会出现在错误信息中
+    pub fn rule<F>(mut self, name: impl Into<String>, predicate: F) -> Self
+    where
+        F: Fn(&Submission) -> bool + Send + Sync + 'static,
+    {
+        self.rules.push((name.into(), Arc::new(predicate)));
+        self
+    }
+
+    // queued 是当前的排队任务数, same_level 是其中与 submission 同一命名级别的任务数
+    pub(crate) fn check(&self, submission: &Submission, queued: usize, same_level: usize) -> Result<(), TaskError> {
+        if let (Some(max), Some(estimate)) = (self.max_estimate, submission.estimate)
+            && estimate > max
+        {
+            return Err(TaskError::Rejected(format!("预估运行时间 {:?} 超过上限 {:?}", estimate, max)));
+        }
+
+        if let Some(max) = self.max_queued
+            && queued >= max
+        {
+            return Err(TaskError::Rejected(format!("排队任务数已达上限 {}", max)));
+        }
+        let level = submission.priority.level();
+        if let Some(max) = self.quotas.get(&level)
+            && same_level >= *max
+        {
+            return Err(TaskError::Rejected(format!("{:?} 级别的排队任务数已达上限 {}", level, max)));
+        }
+
+        match self.rules.iter().find(|(_, predicate)| !predicate(submission)) {
+            Some((name, _)) => Err(TaskError::Rejected(format!("不满足规则 \"{}\"", name))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for AdmissionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdmissionPolicy")
+            .field("max_estimate", &self.max_estimate)
+            .field("max_queued", &self.max_queued)
+            .field("quotas", &self.quotas)
+            .field("rules", &self.rules.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .finish()
+    }
+}
@@ -0,0 +1,2535 @@
+use std::{
+    any::Any,
+    cmp,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    error::Error,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+mod admission;
+mod policy;
+mod schedule;
+
+pub use admission::{AdmissionPolicy, Submission};
+pub use policy::{
+    EarliestDeadlineFirst, FairShare, FifoPolicy, MultilevelFeedback, PriorityPolicy, SchedulingPolicy, ShortestJobFirst, TaskInfo,
+    WeightedFairQueuing,
+};
+pub use schedule::{CronExpr, OverlapPolicy, Schedule};
+
+pub const COLOR_REST: &str = "\x1b[0m";
+pub const COLOR_RED: &str = "\x1b[31m";
+pub const COLOR_GREEN: &str = "\x1b[32m";
+pub const COLOR_YELLOW: &str = "\x1b[33m";
+
+// 自定义错误类型
+// dervie 自动实现 trait ("接口")
+#[derive(Debug)]
+pub enum TaskError {
+    ExecutionError(String), // 运行错误, 视为暂时性错误
+    TimeOut,
+    NotFound, // 任务编号不存在
+    ShutDown, // 调度器已关闭, 不再接受新任务
+    Cancelled, // 任务被取消或移除, 没有运行完
+    Failed(TaskFailure), // 带分类, 错误链和上下文的运行错误
+    DependencyFailed(TaskId), // 上游任务没有成功, 任务被跳过
+    DependencyCycle,          // 任务依赖形成了环
+    InvalidSchedule(String),  // 周期任务的触发规则不合法
+    DeadlineMissed,           // 任务没能在截止时间前完成
+    DeadlineUnreachable,      // 按预估运行时间无法在截止时间前完成, 提交时被拒绝
+    Rejected(String),         // 不满足准入策略, 提交时被拒绝
+    QueueFull,                // 队列已满, 提交时被拒绝
+    Evicted,                  // 队列已满时被优先级更高的新任务挤出
+}
+
+// 错误分类, 重试, 告警等逻辑据此决定如何处理, 不需要解析错误信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Transient, // 暂时性错误, 重试可能成功
+    Fatal,     // 永久性错误, 重试没有意义
+}
+
+// 详细的运行错误
+#[derive(Debug)]
+pub struct TaskFailure {
+    kind: ErrorKind,
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync>>, // 引起这个错误的底层错误
+    context: Vec<(&'static str, String)>,         // 结构化的上下文字段, 例如 ("table", "users")
+}
+
+impl TaskError {
+    // 暂时性错误, 配置了重试策略时会被重试
+    pub fn transient(message: impl Into<String>) -> Self {
+        TaskError::Failed(TaskFailure { kind: ErrorKind::Transient, message: message.into(), source: None, context: Vec::new() })
+    }
+
+    // 永久性错误, 不会被重试
+    pub fn fatal(message: impl Into<String>) -> Self {
+        TaskError::Failed(TaskFailure { kind: ErrorKind::Fatal, message: message.into(), source: None, context: Vec::new() })
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            TaskError::ExecutionError(_) | TaskError::TimeOut | TaskError::QueueFull => ErrorKind::Transient,
+            TaskError::NotFound
+            | TaskError::ShutDown
+            | TaskError::Cancelled
+            | TaskError::DependencyFailed(_)
+            | TaskError::DependencyCycle
+            | TaskError::InvalidSchedule(_)
+            | TaskError::DeadlineMissed
+            | TaskError::DeadlineUnreachable
+            | TaskError::Rejected(_)
+            | TaskError::Evicted => ErrorKind::Fatal,
+            TaskError::Failed(failure) => failure.kind,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind() == ErrorKind::Transient
+    }
+
+    // 设置底层错误, 之后可以通过 Error::source 沿着错误链查看
+    pub fn with_source(self, source: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        let mut failure = self.into_failure();
+        failure.source = Some(source.into());
+        TaskError::Failed(failure)
+    }
+
+    // 添加一个上下文字段
+    pub fn with_context(self, key: &'static str, value: impl ToString) -> Self {
+        let mut failure = self.into_failure();
+        failure.context.push((key, value.to_string()));
+        TaskError::Failed(failure)
+    }
+
+    // 上下文字段, 只有 Failed 才有
+    pub fn context(&self) -> &[(&'static str, String)] {
+        match self {
+            TaskError::Failed(failure) => &failure.context,
+            _ => &[],
+        }
+    }
+
+    // 转换成 TaskFailure 以便附加信息, 保持原有的分类
+    // ExecutionError 保留错误信息, 其他变体整体作为底层错误保存下来
+    fn into_failure(self) -> TaskFailure {
+        let kind = self.kind();
+        match self {
+            TaskError::Failed(failure) => failure,
+            TaskError::ExecutionError(message) => TaskFailure { kind, message, source: None, context: Vec::new() },
+            other => TaskFailure { kind, message: other.to_string(), source: Some(Box::new(other)), context: Vec::new() },
+        }
+    }
+}
+
+// 为枚举类 TaskError 实现 fmt::Display
+impl fmt::Display for TaskError {
+    // &self: taskerror 不可变引用  &mut: 可变引用 <'_>: 生命周期为这个函数 f = formatter: 格式化工具
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::ExecutionError(msg) => write!(f, "执行任务失败: {}", msg),
+            TaskError::TimeOut => write!(f, "任务超时"),
+            TaskError::NotFound => write!(f, "找不到任务"),
+            TaskError::ShutDown => write!(f, "调度器已关闭"),
+            TaskError::Cancelled => write!(f, "任务已取消"),
+            TaskError::DependencyFailed(upstream) => write!(f, "上游任务 {} 没有成功, 跳过", upstream),
+            TaskError::DependencyCycle => write!(f, "任务依赖存在环"),
+            TaskError::InvalidSchedule(msg) => write!(f, "触发规则不合法: {}", msg),
+            TaskError::DeadlineMissed => write!(f, "任务错过了截止时间"),
+            TaskError::DeadlineUnreachable => write!(f, "任务无法在截止时间前完成"),
+            TaskError::Rejected(reason) => write!(f, "任务被拒绝: {}", reason),
+            TaskError::QueueFull => write!(f, "任务队列已满"),
+            TaskError::Evicted => write!(f, "队列已满, 任务被挤出"),
+            TaskError::Failed(failure) => {
+                write!(f, "执行任务失败: {}", failure.message)?;
+                for (key, value) in &failure.context {
+                    write!(f, " {}={}", key, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// 实现标准库的 Error, 可以和 ? 以及其他错误处理库一起使用
+impl Error for TaskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TaskError::Failed(failure) => failure.source.as_deref().map(|source| source as &(dyn Error + 'static)),
+            _ => None,
+        }
+    }
+}
+
+// 任务优先级, 0-255, 数值越大越先运行
+// 自动实现 .clone(), 逻辑判断 == / !=, 以及按数值比较大小 < / >
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Priority(u8);
+
+// 命名的优先级, 保持和原来的枚举写法 Priority::High 一致
+#[allow(non_upper_case_globals)]
+impl Priority {
+    pub const Critical: Priority = Priority(255);
+    pub const High: Priority = Priority(192);
+    pub const Medium: Priority = Priority(128);
+    pub const Low: Priority = Priority(64);
+    pub const Background: Priority = Priority(0);
+}
+
+// 从低到高的命名级别
+const PRIORITY_LEVELS: [(Priority, &str); 5] = [
+    (Priority::Background, "Background"),
+    (Priority::Low, "Low"),
+    (Priority::Medium, "Medium"),
+    (Priority::High, "High"),
+    (Priority::Critical, "Critical"),
+];
+
+impl Priority {
+    // 自定义优先级, 例如 Priority::new(200) 介于 High 和 Critical 之间
+    pub const fn new(value: u8) -> Self {
+        Priority(value)
+    }
+
+    pub fn value(self) -> u8 {
+        self.0
+    }
+
+    // 不高于当前优先级的最高命名级别
+    fn level(self) -> Priority {
+        PRIORITY_LEVELS.iter().rev().map(|(level, _)| *level).find(|level| *level <= self).unwrap_or(Priority::Background)
+    }
+
+    // 比当前优先级高的下一个命名级别, 已经是最高级时为 None
+    fn next_level(self) -> Option<Priority> {
+        PRIORITY_LEVELS.iter().map(|(level, _)| *level).find(|level| *level > self)
+    }
+}
+
+impl From<u8> for Priority {
+    fn from(value: u8) -> Self {
+        Priority(value)
+    }
+}
+
+// 命名级别打印名字, 例如 High; 自定义的数值打印为 Priority(200)
+impl fmt::Debug for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match PRIORITY_LEVELS.iter().find(|(level, _)| level == self) {
+            Some((_, name)) => write!(f, "{}", name),
+            None => write!(f, "Priority({})", self.0),
+        }
+    }
+}
+
+// 任务的输出值, 类型被擦除, 通过 downcast 取回具体类型
+// 使用 Arc 是为了让多个读取方 (任务句柄, 调度器查询) 共享同一个输出
+pub type TaskOutput = Arc<dyn Any + Send + Sync>;
+
+// 任务运行时的上下文, 调度器通过它通知任务停止 (例如超时), 任务通过它提交输出值
+// 内部是 Arc, clone 之后指向同一个取消标记和输出
+#[derive(Clone, Default)]
+pub struct TaskContext {
+    cancelled: Arc<AtomicBool>,
+    output: Arc<Mutex<Option<TaskOutput>>>,
+    inputs: Arc<Mutex<Vec<(TaskId, TaskOutput)>>>, // 上游任务的输出, 按声明依赖的顺序
+}
+
+impl TaskContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 新的一次尝试使用新的取消标记和输出, 上游输出保持不变
+    fn renew(&self) -> Self {
+        TaskContext {
+            inputs: Arc::clone(&self.inputs),
+            ..Self::default()
+        }
+    }
+
+    // 上游任务的输出值, 按声明依赖的顺序
+    pub fn inputs(&self) -> Vec<(TaskId, TaskOutput)> {
+        self.inputs.lock().unwrap().clone()
+    }
+
+    // 指定上游任务的输出值
+    pub fn input(&self, upstream: TaskId) -> Option<TaskOutput> {
+        self.inputs.lock().unwrap().iter().find(|(id, _)| *id == upstream).map(|(_, output)| Arc::clone(output))
+    }
+
+    // 第一个类型为 T 的上游输出值
+    pub fn input_as<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.inputs.lock().unwrap().iter().find_map(|(_, output)| Arc::clone(output).downcast::<T>().ok())
+    }
+
+    // 标记任务需要停止
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    // 设置任务的输出值, 多次调用时以最后一次为准
+    pub fn set_output<T: Any + Send + Sync>(&self, value: T) {
+        *self.output.lock().unwrap() = Some(Arc::new(value));
+    }
+
+    // 取出输出值, 任务没有设置时为 ()
+    fn take_output(&self) -> TaskOutput {
+        self.output.lock().unwrap().take().unwrap_or_else(|| Arc::new(()))
+    }
+
+    // 任务应在合适的时机检查, 被取消后尽快返回
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // 可被取消的 sleep, 按小段休眠以便及时响应取消
+    pub fn sleep(&self, duration: Duration) -> Result<(), TaskError> {
+        const STEP: Duration = Duration::from_millis(50);
+        let end = Instant::now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return Err(TaskError::Cancelled);
+            }
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            thread::sleep(STEP.min(end - now));
+        }
+    }
+}
+
+// 按时间片运行的结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SliceOutcome {
+    Finished, // 任务已完成
+    Yielded,  // 时间片用完, 任务还没完成, 下次从停下的位置继续
+}
+
+// 特性: 接口
+// send: 所有权可以转移 sync: 可以被多线程共享
+pub trait Executable: Send + Sync {
+    fn execute(&self, ctx: &TaskContext) -> Result<(), TaskError>;
+    fn get_name(&self) -> String;
+
+    // 可恢复的运行接口, RR 模式下使用: 最多运行 quantum 时间后返回
+    // 任务需要自己保存进度, 默认不支持分片, 直接完整运行一次
+    fn run_slice(&self, ctx: &TaskContext, quantum: Duration) -> Result<SliceOutcome, TaskError> {
+        let _ = quantum;
+        self.execute(ctx).map(|_| SliceOutcome::Finished)
+    }
+
+    // 预估运行时间, 没有通过 TaskOptions::estimate 指定时由调度策略使用
+    fn estimated_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+pub struct SimpleTask {
+    name: String,
+    duration_secs: u64,
+    progress_ns: AtomicU64, // 按时间片运行时已完成的纳秒数
+}
+
+impl SimpleTask {
+    pub fn new(name: String, duration_secs: u64) -> Self {
+        SimpleTask {
+            name,
+            duration_secs,
+            progress_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+// 为 simpletask 实现 executable 特性, result 是枚举
+impl Executable for SimpleTask {
+    fn execute(&self, ctx: &TaskContext) -> Result<(), TaskError> {
+        println!("正在运行任务: {}", self.name);
+        ctx.sleep(Duration::from_secs(self.duration_secs))
+    }
+
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn estimated_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.duration_secs))
+    }
+
+    // 分片运行时不再拒绝长任务, 每次只运行一个时间片
+    fn run_slice(&self, ctx: &TaskContext, quantum: Duration) -> Result<SliceOutcome, TaskError> {
+        let total = Duration::from_secs(self.duration_secs);
+        let done = Duration::from_nanos(self.progress_ns.load(Ordering::SeqCst));
+        if done.is_zero() {
+            println!("正在运行任务: {}", self.name);
+        } else {
+            println!("继续运行任务: {} (已完成 {:?} / {:?})", self.name, done, total);
+        }
+
+        let slice = (total - done).min(quantum);
+        ctx.sleep(slice)?;
+        let done = done + slice;
+        self.progress_ns.store(done.as_nanos() as u64, Ordering::SeqCst);
+
+        if done >= total {
+            Ok(SliceOutcome::Finished)
+        } else {
+            Ok(SliceOutcome::Yielded)
+        }
+    }
+}
+
+// 默认工作线程数
+const DEFAULT_WORKERS: usize = 4;
+
+// 失败重试策略, 重试间隔按指数增长:
+// 第 n 次失败后等待 base_delay * multiplier^(n-1), 再加上随机抖动, 最多不超过 max_delay
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32, // 最多尝试次数, 包括第一次运行
+    base_delay: Duration,
+    multiplier: f64,
+    jitter: f64, // 抖动比例 0.0 ~ 1.0, 例如 0.2 表示在 ±20% 范围内随机
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // 默认第一次重试等待 100ms, 之后每次翻倍, 没有抖动, 最多等待 30s
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay: Duration::from_millis(100),
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_delay: Duration::from_secs(30),
+        }
+    }
+
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    // 第 attempt 次尝试失败后的等待时间, 已经用完尝试次数时返回 None
+    fn delay_after(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let max_secs = self.max_delay.as_secs_f64();
+        let delay = (self.base_delay.as_secs_f64() * self.multiplier.powi(exponent)).min(max_secs);
+        let factor = 1.0 + self.jitter * (2.0 * random_unit() - 1.0);
+        Some(Duration::from_secs_f64((delay * factor).clamp(0.0, max_secs)))
+    }
+}
+
+// [0, 1) 之间的伪随机数, 用于重试抖动, 不需要密码学强度
+fn random_unit() -> f64 {
+    static SEED: AtomicU64 = AtomicU64::new(0);
+    let mut x = SEED.load(Ordering::Relaxed);
+    if x == 0 {
+        // 第一次使用时按照系统时间生成种子
+        x = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x2545_f491_4f6c_dd1d)
+            | 1;
+    }
+    // xorshift 伪随机
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    SEED.store(x, Ordering::Relaxed);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// 优先级老化: 任务在队列中等待一段时间后有效优先级提升一级, 避免低优先级任务一直等不到运行
+// 每一级单独设置等待时间, 没有设置的级别不会提升
+#[derive(Debug, Clone, Default)]
+pub struct AgingPolicy {
+    after: HashMap<Priority, Duration>, // 命名级别 -> 提升到下一个命名级别需要等待的时间
+}
+
+impl AgingPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // level 所在命名级别的任务等待 after 后提升到下一个命名级别 (例如 Low -> Medium)
+    // 自定义的数值按不高于它的命名级别计算, Critical 已经是最高级, 设置没有效果
+    pub fn promote_after(mut self, level: Priority, after: Duration) -> Self {
+        self.after.insert(level.level(), after);
+        self
+    }
+
+    // 等待 waited 之后的有效优先级, 以及距离下一次提升还要等多久
+    fn effective(&self, base: Priority, waited: Duration) -> (Priority, Option<Duration>) {
+        let mut level = base;
+        let mut remaining = waited;
+        loop {
+            let (Some(after), Some(higher)) = (self.after.get(&level.level()), level.next_level()) else {
+                return (level, None);
+            };
+            if remaining < *after {
+                return (level, Some(*after - remaining));
+            }
+            remaining -= *after;
+            level = higher;
+        }
+    }
+}
+
+// 任务的可选配置, 通过链式调用设置
+#[derive(Debug, Clone, Default)]
+pub struct TaskOptions {
+    timeout: Option<Duration>,   // 每次尝试的超时时间, None 表示不限制
+    retry: Option<RetryPolicy>,  // 失败后的重试策略, None 表示不重试
+    dependencies: Vec<TaskId>,   // 上游任务, 全部成功后才会运行
+    start_at: Option<Instant>,   // 最早的运行时间, None 表示立即可以运行
+    deadline: Option<Instant>,   // 必须完成的时间, 到时还没完成的任务被取消
+    estimate: Option<Duration>,  // 预估运行时间, 用于提交时检查能否赶上截止时间
+    tenant: Option<String>,      // 所属租户, None 表示 DEFAULT_TENANT
+}
+
+// 没有指定租户的任务归入这个租户
+pub const DEFAULT_TENANT: &str = "default";
+
+impl TaskOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    // 到指定时间才可以运行, 有依赖时还需要等上游任务完成
+    pub fn start_at(mut self, at: Instant) -> Self {
+        self.start_at = Some(at);
+        self
+    }
+
+    // 截止时间: 到时还没开始的任务不再运行, 正在运行的任务被取消, 结果为 DeadlineMissed
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // 预估运行时间, 同时设置了截止时间时, 提交时就能发现赶不上的任务
+    pub fn estimate(mut self, estimate: Duration) -> Self {
+        self.estimate = Some(estimate);
+        self
+    }
+
+    // 所属租户 (团队, 队列等), FairShare 策略按租户分配运行时间
+    pub fn tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    fn tenant_name(&self) -> &str {
+        self.tenant.as_deref().unwrap_or(DEFAULT_TENANT)
+    }
+
+    // 声明依赖, 上游任务成功后才会运行, 上游失败时任务被跳过
+    pub fn depends_on(mut self, upstream: TaskId) -> Self {
+        if !self.dependencies.contains(&upstream) {
+            self.dependencies.push(upstream);
+        }
+        self
+    }
+}
+
+// 任务编号, 由调度器在添加任务时分配, 不会重复
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+// 周期任务编号, 每次触发运行的任务实例另有自己的 TaskId
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecurringId(u64);
+
+impl fmt::Display for RecurringId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "R#{}", self.0)
+    }
+}
+
+// 周期任务每次触发时用它创建新的任务实例
+pub type TaskFactory = Arc<dyn Fn() -> Box<dyn Executable> + Send + Sync>;
+
+// 注册的周期任务
+struct RecurringTask {
+    priority: Priority,
+    factory: TaskFactory,
+    options: TaskOptions,
+    schedule: Schedule,
+    overlap: OverlapPolicy,
+    next_fire: Option<SystemTime>, // None 表示不会再触发
+    runs: Vec<TaskId>,             // 还没结束的任务实例, 结束时移除
+    backlog: usize,                // Queue 策略下等待上一次运行结束的触发次数
+}
+
+// 任务图中的节点, 提交前还没有任务编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeKey {
+    graph: u64, // 所属任务图的编号, 用来发现其他图的节点
+    index: usize,
+}
+
+// 任务图编号, 每个 TaskGraph 不同
+static NEXT_GRAPH_ID: AtomicU64 = AtomicU64::new(0);
+
+struct GraphNode {
+    priority: Priority,
+    task: Box<dyn Executable>,
+    options: TaskOptions,
+}
+
+// 一组有依赖关系的任务, 通过 Scheduler::add_graph 一次提交
+pub struct TaskGraph {
+    id: u64,
+    nodes: Vec<GraphNode>,
+    edges: Vec<(usize, usize)>, // (上游, 下游)
+    invalid_edge: bool,         // depends_on 用到了不属于这个图的节点, add_graph 时返回 NotFound
+}
+
+impl Default for TaskGraph {
+    fn default() -> Self {
+        TaskGraph {
+            id: NEXT_GRAPH_ID.fetch_add(1, Ordering::Relaxed),
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            invalid_edge: false,
+        }
+    }
+}
+
+impl TaskGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // options 中也可以用 depends_on 依赖图外已经提交的任务
+    pub fn add(&mut self, priority: Priority, task: Box<dyn Executable>, options: TaskOptions) -> NodeKey {
+        self.nodes.push(GraphNode { priority, task, options });
+        NodeKey { graph: self.id, index: self.nodes.len() - 1 }
+    }
+
+    // node 在 upstream 成功后才会运行
+    pub fn depends_on(&mut self, node: NodeKey, upstream: NodeKey) -> &mut Self {
+        if !self.contains(node) || !self.contains(upstream) {
+            self.invalid_edge = true;
+        } else if !self.edges.contains(&(upstream.index, node.index)) {
+            self.edges.push((upstream.index, node.index));
+        }
+        self
+    }
+
+    fn contains(&self, key: NodeKey) -> bool {
+        key.graph == self.id && key.index < self.nodes.len()
+    }
+
+    // Kahn 算法求拓扑顺序, 有环时返回 None
+    fn topological_order(&self) -> Option<Vec<usize>> {
+        let mut in_degree = vec![0; self.nodes.len()];
+        for &(_, to) in &self.edges {
+            in_degree[to] += 1;
+        }
+        let mut ready: Vec<usize> = (0..self.nodes.len()).filter(|&i| in_degree[i] == 0).collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(node) = ready.pop() {
+            order.push(node);
+            for &(from, to) in &self.edges {
+                if from == node {
+                    in_degree[to] -= 1;
+                    if in_degree[to] == 0 {
+                        ready.push(to);
+                    }
+                }
+            }
+        }
+        (order.len() == self.nodes.len()).then_some(order)
+    }
+}
+
+// 任务状态
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskStatus {
+    Queued { effective: Priority }, // 排队中 (包括 RR 模式下等待下一个时间片), effective 是包含老化提升的有效优先级
+    Waiting,        // 等待到期 (延迟任务或重试退避), 到期后入队
+    Blocked,        // 等待上游任务完成
+    Running,        // 运行中
+    Completed,      // 运行成功
+    Failed(String), // 运行失败, 保存错误信息
+    Cancelled,      // 被取消
+    Skipped,        // 上游任务没有成功, 没有运行
+    DeadlineMissed, // 没能在截止时间前完成
+}
+
+// 同优先级任务的出队顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueOrder {
+    #[default]
+    Fifo, // 先提交先运行
+    Lifo, // 后提交先运行
+}
+
+// 队列中的任务
+// 任务用 Arc 保存, 超时监控需要把它交给单独的线程运行
+struct QueuedTask {
+    id: TaskId,
+    priority: Priority,
+    effective: Priority,   // 老化之后的有效优先级, 调度策略按它排序
+    enqueued_at: Instant,  // 进入就绪队列的时间, 老化从这里开始计算
+    task: Arc<dyn Executable>,
+    options: TaskOptions,
+    ran_for: Duration, // 本次尝试已经运行过的时间 (RR 模式下累计多个时间片)
+    attempt: u32,      // 第几次尝试, 从 1 开始
+    seq: u64,          // 入队序号, 每次入队 (包括 RR 重新入队) 递增, 同优先级时决定出队顺序
+    requeued: bool,    // 是否是 RR 时间片用完后重新入队的任务
+}
+
+impl QueuedTask {
+    // 预估运行时间: TaskOptions::estimate, 没有时使用任务自己声明的
+    fn estimate(&self) -> Option<Duration> {
+        self.options.estimate.or_else(|| self.task.estimated_duration())
+    }
+
+    // 交给调度策略的调度信息
+    fn info(&self) -> TaskInfo {
+        TaskInfo {
+            id: self.id,
+            name: self.task.get_name(),
+            priority: self.effective,
+            seq: self.seq,
+            requeued: self.requeued,
+            enqueued_at: self.enqueued_at,
+            deadline: self.options.deadline,
+            estimate: self.estimate(),
+            ran_for: self.ran_for,
+            tenant: self.options.tenant_name().to_string(),
+        }
+    }
+}
+
+// 等待一段时间后才能入队的任务 (延迟任务, 重试退避)
+struct DelayedTask {
+    due: Instant, // 到期时间
+    seq: u64,     // 到期时间相同时按加入顺序
+    task: QueuedTask,
+}
+
+// 堆顶是最早到期的任务
+impl Ord for DelayedTask {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        other.due.cmp(&self.due).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for DelayedTask {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for DelayedTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for DelayedTask {}
+
+// 任务的最终结果, 成功时带有输出值
+pub type TaskResult = Result<TaskOutput, TaskError>;
+
+// 任务结果的存放位置, 任务结束时由调度器写入, TaskHandle 从这里读取
+#[derive(Debug, Default)]
+struct ResultSlot {
+    result: Mutex<Option<TaskResult>>,
+    finished: AtomicBool, // 结果被取走后仍然为 true
+    attempts: AtomicU32,  // 已经开始的尝试次数
+    ready: Condvar,
+}
+
+impl ResultSlot {
+    // 只有第一次写入有效
+    fn set(&self, result: TaskResult) {
+        let mut slot = self.result.lock().unwrap();
+        if self.finished.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        *slot = Some(result);
+        self.ready.notify_all();
+    }
+}
+
+// add_task 返回的任务句柄, 用来等待或轮询任务结果
+// 结果只能取走一次, 之后 try_result 返回 None
+#[derive(Debug)]
+pub struct TaskHandle {
+    id: TaskId,
+    slot: Arc<ResultSlot>,
+}
+
+impl TaskHandle {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    // 已经开始运行的次数, 包括重试
+    pub fn attempts(&self) -> u32 {
+        self.slot.attempts.load(Ordering::SeqCst)
+    }
+
+    // 任务是否已经结束 (成功, 失败或被取消)
+    pub fn is_finished(&self) -> bool {
+        self.slot.finished.load(Ordering::SeqCst)
+    }
+
+    // 轮询: 任务已结束时取走结果, 否则立即返回 None
+    pub fn try_result(&self) -> Option<TaskResult> {
+        self.slot.result.lock().unwrap().take()
+    }
+
+    // 最多等待 timeout, 任务在此期间结束时取走结果
+    pub fn join_timeout(&self, timeout: Duration) -> Option<TaskResult> {
+        let slot = self.slot.result.lock().unwrap();
+        let (mut slot, _) = self.slot.ready
+            .wait_timeout_while(slot, timeout, |_| !self.slot.finished.load(Ordering::SeqCst))
+            .unwrap();
+        slot.take()
+    }
+
+    // 阻塞直到任务结束, 返回任务结果
+    // 结果已经被 try_result / join_timeout 取走时返回 NotFound
+    pub fn join(self) -> TaskResult {
+        let slot = self.slot.result.lock().unwrap();
+        let mut slot = self.slot.ready
+            .wait_while(slot, |_| !self.slot.finished.load(Ordering::SeqCst))
+            .unwrap();
+        slot.take().unwrap_or(Err(TaskError::NotFound))
+    }
+
+    // 阻塞直到任务结束, 并把输出值还原成具体类型 T
+    pub fn join_as<T: Any + Send + Sync>(self) -> Result<Arc<T>, TaskError> {
+        downcast_output(self.join()?)
+    }
+}
+
+// 把类型擦除的输出还原成具体类型
+fn downcast_output<T: Any + Send + Sync>(output: TaskOutput) -> Result<Arc<T>, TaskError> {
+    output.downcast::<T>().map_err(|_| {
+        TaskError::fatal("任务输出类型不匹配").with_context("expected", std::any::type_name::<T>())
+    })
+}
+
+// 调度器为每个任务保存的记录, 任务结束后仍然保留, 用于查询状态
+struct TaskRecord {
+    status: TaskStatus,
+    priority: Priority,     // 提交时的优先级
+    ctx: TaskContext,       // 运行中的任务通过它取消
+    cancel_requested: bool, // 用户调用了 cancel, 区分超时引起的取消
+    slot: Arc<ResultSlot>,  // 与 TaskHandle 共享
+    output: Option<TaskOutput>, // 运行成功后的输出值, 供 Scheduler::output 查询
+    recurring: Option<RecurringId>, // 周期任务的实例所属的周期任务, 结束后不保留记录
+}
+
+impl TaskRecord {
+    // 任务结束: 根据结果更新状态, 并把结果交给 TaskHandle
+    fn finish(&mut self, result: TaskResult) {
+        if let Ok(output) = &result {
+            self.output = Some(Arc::clone(output));
+        }
+        self.status = match &result {
+            Ok(_) => TaskStatus::Completed,
+            Err(TaskError::Cancelled | TaskError::Evicted) => TaskStatus::Cancelled,
+            Err(TaskError::DependencyFailed(_)) => TaskStatus::Skipped,
+            Err(TaskError::DeadlineMissed) => TaskStatus::DeadlineMissed,
+            Err(e) => TaskStatus::Failed(e.to_string()),
+        };
+        self.slot.set(result);
+    }
+}
+
+// 等待上游任务的任务
+struct BlockedTask {
+    task: QueuedTask,
+    waiting_on: usize, // 还没完成的上游任务数
+}
+
+// 调度器的共享状态, 队列和任务记录由同一把锁保护, 保证两者一致
+struct SchedulerState {
+    ready: HashMap<TaskId, QueuedTask>,  // 就绪的任务
+    policy: Box<dyn SchedulingPolicy>,   // 决定就绪任务的出队顺序
+    delayed: BinaryHeap<DelayedTask>, // 还没到期的任务, 按到期时间排序
+    blocked: HashMap<TaskId, BlockedTask>,     // 等待上游任务的任务
+    dependents: HashMap<TaskId, Vec<TaskId>>,  // 上游任务 -> 等待它的下游任务
+    records: HashMap<TaskId, TaskRecord>,
+    recurring: HashMap<RecurringId, RecurringTask>,
+    timers: BinaryHeap<cmp::Reverse<(Instant, RecurringId)>>, // 周期任务的下一次触发时间, 堆顶最早
+    aging: Option<AgingPolicy>,
+    admission: Option<AdmissionPolicy>,
+    capacity: Option<(usize, OverflowPolicy)>, // 排队任务数上限和队列满时的处理方式
+    next_aging: Option<Instant>, // 队列中最早有任务需要提升优先级的时间
+    next_id: u64,
+    next_recurring_id: u64,
+    next_seq: u64,
+    running: usize,                  // 正在运行的任务数
+    queued_levels: HashMap<Priority, usize>, // 各命名级别的排队任务数, 准入检查不用遍历队列
+    consumed: HashMap<String, Duration>, // 每个租户的任务累计运行时间
+    shutdown: Option<ShutdownMode>,  // 开始关闭后不再接受新任务, 工作线程处理完队列后退出
+}
+
+impl SchedulerState {
+    // 创建任务记录, 根据上游任务的状态放入就绪队列, 阻塞等待或直接跳过
+    fn submit(&mut self, priority: Priority, task: Arc<dyn Executable>, options: TaskOptions) -> TaskHandle {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+
+        let slot = Arc::new(ResultSlot::default());
+        self.records.insert(id, TaskRecord {
+            status: TaskStatus::Queued { effective: priority },
+            priority,
+            ctx: TaskContext::new(),
+            cancel_requested: false,
+            slot: Arc::clone(&slot),
+            output: None,
+            recurring: None,
+        });
+
+        // 上游任务中已经失败的, 和还没有结束的
+        let mut failed_upstream = None;
+        let mut pending = Vec::new();
+        for upstream in &options.dependencies {
+            // 周期任务的实例提交时, 上游任务可能已经被 remove
+            match self.records.get(upstream).map(|record| &record.status) {
+                Some(TaskStatus::Completed) => {}
+                None | Some(TaskStatus::Failed(_) | TaskStatus::Cancelled | TaskStatus::Skipped | TaskStatus::DeadlineMissed) => {
+                    failed_upstream = Some(*upstream)
+                }
+                Some(_) => pending.push(*upstream),
+            }
+        }
+
+        let queued = QueuedTask {
+            id,
+            effective: priority,
+            priority,
+            enqueued_at: Instant::now(),
+            task,
+            options,
+            ran_for: Duration::ZERO,
+            attempt: 1,
+            seq: 0,
+            requeued: false,
+        };
+        if let Some(upstream) = failed_upstream {
+            self.settle(id, Err(TaskError::DependencyFailed(upstream)));
+        } else if pending.is_empty() {
+            self.collect_inputs(&queued);
+            self.schedule(queued);
+        } else {
+            self.records.get_mut(&id).unwrap().status = TaskStatus::Blocked;
+            for upstream in &pending {
+                self.dependents.entry(*upstream).or_default().push(id);
+            }
+            self.track_queued(priority, true);
+            self.blocked.insert(id, BlockedTask { task: queued, waiting_on: pending.len() });
+        }
+        TaskHandle { id, slot }
+    }
+
+    // 没有排队, 等待中, 被阻塞和运行中的任务
+    fn is_idle(&self) -> bool {
+        self.ready.is_empty() && self.delayed.is_empty() && self.blocked.is_empty() && self.running == 0
+    }
+
+    // 分配新的入队序号并放入就绪队列
+    fn push_ready(&mut self, mut task: QueuedTask) {
+        if let Some(record) = self.records.get_mut(&task.id) {
+            record.status = TaskStatus::Queued { effective: task.priority };
+        }
+        task.seq = self.next_seq;
+        task.requeued = false;
+        self.next_seq += 1;
+        self.enqueue(task);
+    }
+
+    // 放入就绪队列, 重新开始计算老化时间
+    fn enqueue(&mut self, mut task: QueuedTask) {
+        task.enqueued_at = Instant::now();
+        task.effective = task.priority;
+        if let Some(aging) = &self.aging
+            && let (_, Some(wait)) = aging.effective(task.priority, Duration::ZERO)
+        {
+            let at = task.enqueued_at + wait;
+            self.next_aging = Some(self.next_aging.map_or(at, |next| next.min(at)));
+        }
+        self.policy.push(task.info());
+        self.track_queued(task.priority, true);
+        self.ready.insert(task.id, task);
+    }
+
+    // 按调度策略取出下一个就绪任务
+    fn pop_ready(&mut self) -> Option<QueuedTask> {
+        let id = self.policy.pop()?;
+        let task = self.ready.remove(&id)?;
+        self.track_queued(task.priority, false);
+        Some(task)
+    }
+
+    // 从就绪队列中移除任务
+    fn remove_ready(&mut self, id: TaskId) -> Option<QueuedTask> {
+        self.policy.remove(id);
+        let task = self.ready.remove(&id)?;
+        self.track_queued(task.priority, false);
+        Some(task)
+    }
+
+    // 任务进入 (entered 为 true) 或离开就绪队列, 等待队列和阻塞的任务时更新排队计数
+    fn track_queued(&mut self, priority: Priority, entered: bool) {
+        let count = self.queued_levels.entry(priority.level()).or_default();
+        if entered {
+            *count += 1;
+        } else {
+            *count -= 1;
+        }
+    }
+
+    // upstream 以及它们还在等待的上游任务, 只有阻塞中的任务还有没结束的上游
+    fn pending_ancestors(&self, upstream: &[TaskId]) -> HashSet<TaskId> {
+        let mut ancestors = HashSet::new();
+        let mut stack = upstream.to_vec();
+        while let Some(id) = stack.pop() {
+            if ancestors.insert(id)
+                && let Some(blocked) = self.blocked.get(&id)
+            {
+                stack.extend(&blocked.task.options.dependencies);
+            }
+        }
+        ancestors
+    }
+
+    // 排队任务总数: 就绪, 延迟和等待上游的任务, 不包括正在运行的任务
+    fn queued_count(&self) -> usize {
+        self.ready.len() + self.delayed.len() + self.blocked.len()
+    }
+
+    // 有任务到了提升优先级的时间时, 重新计算所有排队任务的有效优先级, 提升的任务重新交给调度策略
+    // 只在有任务需要提升时计算, 平时出队不受影响
+    fn apply_aging(&mut self) {
+        let now = Instant::now();
+        let (Some(aging), Some(next)) = (&self.aging, self.next_aging) else {
+            return;
+        };
+        if next > now {
+            return;
+        }
+        let mut next_aging: Option<Instant> = None;
+        for task in self.ready.values_mut() {
+            let (effective, wait) = aging.effective(task.priority, now - task.enqueued_at);
+            if effective != task.effective {
+                println!("{}优先级提升:{} {} {:?} -> {:?} (已等待 {:?})", COLOR_YELLOW, COLOR_REST, task.id, task.effective, effective, now - task.enqueued_at);
+                task.effective = effective;
+                self.policy.remove(task.id);
+                self.policy.push(task.info());
+            }
+            if let Some(wait) = wait {
+                next_aging = Some(next_aging.map_or(now + wait, |next| next.min(now + wait)));
+            }
+        }
+        self.next_aging = next_aging;
+    }
+
+    // 任务结束: 更新记录, 成功时释放等待它的下游任务, 否则跳过所有下游任务
+    fn settle(&mut self, id: TaskId, result: TaskResult) {
+        let succeeded = result.is_ok();
+        let mut owner = None;
+        if let Some(record) = self.records.get_mut(&id) {
+            record.finish(result);
+            owner = record.recurring;
+        }
+        self.policy.on_settled(id);
+
+        // Queue 策略下上一次运行结束, 运行积压的触发
+        if let Some(recurring) = owner.and_then(|owner| self.recurring.get_mut(&owner)) {
+            recurring.runs.retain(|run| *run != id);
+            if recurring.backlog > 0 && recurring.runs.is_empty() {
+                recurring.backlog -= 1;
+                self.launch(owner.unwrap());
+            }
+        }
+
+        for dependent in self.dependents.remove(&id).unwrap_or_default() {
+            if succeeded {
+                let Some(blocked) = self.blocked.get_mut(&dependent) else {
+                    continue;
+                };
+                blocked.waiting_on -= 1;
+                if blocked.waiting_on == 0 {
+                    let task = self.blocked.remove(&dependent).unwrap().task;
+                    self.track_queued(task.priority, false);
+                    self.collect_inputs(&task);
+                    self.schedule(task);
+                }
+            } else if let Some(blocked) = self.blocked.remove(&dependent) {
+                self.track_queued(blocked.task.priority, false);
+                println!("{}跳过任务:{} {} 上游任务 {} 没有成功", COLOR_YELLOW, COLOR_REST, dependent, id);
+                self.settle(dependent, Err(TaskError::DependencyFailed(id)));
+            }
+        }
+
+        // 周期任务的实例结束后删除记录, 长期运行时内存不会随触发次数增长
+        if owner.is_some() {
+            self.records.remove(&id);
+        }
+    }
+
+    // 把上游任务的输出交给任务的上下文
+    fn collect_inputs(&self, task: &QueuedTask) {
+        let inputs = task.options.dependencies.iter()
+            .filter_map(|upstream| {
+                let output = self.records.get(upstream)?.output.clone()?;
+                Some((*upstream, output))
+            })
+            .collect();
+        if let Some(record) = self.records.get(&task.id) {
+            *record.ctx.inputs.lock().unwrap() = inputs;
+        }
+    }
+
+    // 任务在 delay 之后才能入队
+    fn push_delayed(&mut self, task: QueuedTask, due: Instant) {
+        if let Some(record) = self.records.get_mut(&task.id) {
+            record.status = TaskStatus::Waiting;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.track_queued(task.priority, true);
+        self.delayed.push(DelayedTask { due, seq, task });
+    }
+
+    // 还没到运行时间的任务进入等待队列, 否则直接入队
+    fn schedule(&mut self, task: QueuedTask) {
+        match task.options.start_at {
+            Some(due) if due > Instant::now() => self.push_delayed(task, due),
+            _ => self.push_ready(task),
+        }
+    }
+
+    // 设置周期任务的下一次触发时间
+    fn set_next_fire(&mut self, id: RecurringId, next: Option<SystemTime>) {
+        let Some(recurring) = self.recurring.get_mut(&id) else {
+            return;
+        };
+        recurring.next_fire = next;
+        if let Some(next) = next {
+            let delay = next.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO);
+            self.timers.push(cmp::Reverse((Instant::now() + delay, id)));
+        }
+    }
+
+    // 周期任务是否有还没结束的实例
+    fn has_active_run(&self, id: RecurringId) -> bool {
+        !self.recurring[&id].runs.is_empty()
+    }
+
+    // 创建周期任务的一个实例并提交, 不满足准入策略的实例不提交, 相当于跳过这次触发
+    fn launch(&mut self, id: RecurringId) {
+        let recurring = &self.recurring[&id];
+        let (priority, options) = (recurring.priority, recurring.options.clone());
+        let task: Arc<dyn Executable> = Arc::from((recurring.factory)());
+        let name = task.get_name();
+        if let Err(e) = self.check_admission(&Submission::new(priority, task.as_ref(), &options), &[]) {
+            println!("{}周期任务 {} 的实例没有提交:{} {} {}", COLOR_YELLOW, id, COLOR_REST, name, e);
+            return;
+        }
+        let handle = self.submit(priority, task, options);
+        println!("{}周期任务 {} 触发:{} 创建任务 {} {}", COLOR_YELLOW, id, COLOR_REST, handle.id, name);
+        // 上游任务已经失败时实例在提交时就结束了
+        if handle.is_finished() {
+            self.records.remove(&handle.id);
+        } else {
+            self.records.get_mut(&handle.id).unwrap().recurring = Some(id);
+            self.recurring.get_mut(&id).unwrap().runs.push(handle.id);
+        }
+    }
+
+    // 触发所有到期的周期任务, 并安排下一次触发
+    fn fire_due_recurring(&mut self) {
+        let now = Instant::now();
+        while self.timers.peek().is_some_and(|timer| timer.0.0 <= now) {
+            let cmp::Reverse((_, id)) = self.timers.pop().unwrap();
+            // 已经取消的周期任务
+            let Some(recurring) = self.recurring.get(&id) else {
+                continue;
+            };
+            let (overlap, schedule, fired) = (recurring.overlap, recurring.schedule.clone(), recurring.next_fire);
+
+            match overlap {
+                OverlapPolicy::Skip if self.has_active_run(id) => {
+                    println!("{}周期任务 {} 上一次运行还没结束, 跳过本次触发{}", COLOR_YELLOW, id, COLOR_REST);
+                }
+                OverlapPolicy::Queue if self.has_active_run(id) => {
+                    self.recurring.get_mut(&id).unwrap().backlog += 1;
+                }
+                _ => self.launch(id),
+            }
+
+            // 错过了多次触发 (例如系统休眠) 时不补跑, 从当前时间继续计算
+            let now = SystemTime::now();
+            let next = fired.and_then(|fired| schedule.next_after(fired))
+                .filter(|next| *next > now)
+                .or_else(|| schedule.next_after(now));
+            self.set_next_fire(id, next);
+        }
+    }
+
+    // 最早需要唤醒工作线程的时间: 等待中的任务到期或周期任务触发
+    fn next_wakeup(&self) -> Option<Instant> {
+        let delayed = self.delayed.peek().map(|delayed| delayed.due);
+        let timer = self.timers.peek().map(|timer| timer.0.0);
+        delayed.into_iter().chain(timer).min()
+    }
+
+    // 触发到期的周期任务, 把已经到期的任务移入就绪队列, 提升等待太久的任务的优先级
+    fn promote_due(&mut self) {
+        self.fire_due_recurring();
+        self.apply_aging();
+        let now = Instant::now();
+        while self.delayed.peek().is_some_and(|delayed| delayed.due <= now) {
+            let task = self.delayed.pop().unwrap().task;
+            self.track_queued(task.priority, false);
+            self.push_ready(task);
+        }
+    }
+
+    // 从就绪队列, 等待队列和阻塞的任务中移除任务
+    fn dequeue(&mut self, id: TaskId) {
+        self.remove_ready(id);
+        let mut removed = None;
+        self.delayed.retain(|delayed| {
+            if delayed.task.id == id {
+                removed = Some(delayed.task.priority);
+            }
+            delayed.task.id != id
+        });
+        let removed = removed.or_else(|| self.blocked.remove(&id).map(|blocked| blocked.task.priority));
+        if let Some(priority) = removed {
+            self.track_queued(priority, false);
+        }
+    }
+
+    // 按准入策略检查, pending 是同一批中已经通过检查的任务, 同样计入排队数
+    fn check_admission(&self, submission: &Submission, pending: &[Submission]) -> Result<(), TaskError> {
+        let Some(admission) = &self.admission else {
+            return Ok(());
+        };
+        let level = submission.priority.level();
+        let total = self.queued_count() + pending.len();
+        let same_level = self.queued_levels.get(&level).copied().unwrap_or(0)
+            + pending.iter().filter(|pending| pending.priority.level() == level).count();
+        admission.check(submission, total, same_level)
+    }
+
+    // 任务状态, 排队中的任务带上当前的有效优先级
+    fn status(&self, id: TaskId) -> Option<TaskStatus> {
+        let record = self.records.get(&id)?;
+        Some(match record.status {
+            TaskStatus::Queued { .. } => TaskStatus::Queued { effective: self.effective_priority(id) },
+            ref status => status.clone(),
+        })
+    }
+
+    // 任务的有效优先级, 就绪队列中的任务包含老化的提升, 任务必须存在
+    fn effective_priority(&self, id: TaskId) -> Priority {
+        match (self.ready.get(&id), &self.aging) {
+            (Some(queued), Some(aging)) => aging.effective(queued.priority, queued.enqueued_at.elapsed()).0,
+            _ => self.records[&id].priority,
+        }
+    }
+
+    // 所有排队中的任务: 就绪, 延迟和等待上游的任务, 不包括正在运行的任务
+    fn queued_tasks(&self) -> impl Iterator<Item = &QueuedTask> {
+        self.ready.values()
+            .chain(self.delayed.iter().map(|delayed| &delayed.task))
+            .chain(self.blocked.values().map(|blocked| &blocked.task))
+    }
+}
+
+// 设置了队列容量时, 队列满后提交新任务的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Block,       // 阻塞提交者直到有空位, try_add_task 不阻塞, 直接返回 QueueFull
+    FailFast,    // 直接返回 TaskError::QueueFull
+    EvictLowest, // 挤出优先级最低的排队任务, 新任务的优先级不比它们高时返回 QueueFull
+}
+
+// 关闭调度器的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    Drain,         // 运行完所有排队中的任务
+    FinishRunning, // 丢弃排队中的任务, 等待运行中的任务结束
+    Immediate,     // 丢弃排队中的任务, 通知运行中的任务取消
+}
+
+// 关闭调度器后的汇总, 包含调度器生命周期内的所有任务 (已 remove 的除外), 按编号排序
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSummary {
+    pub completed: Vec<TaskId>,   // 运行成功
+    pub failed: Vec<TaskId>,      // 运行失败
+    pub dropped: Vec<TaskId>,     // 排队中, 等待重试或等待上游任务时被丢弃
+    pub interrupted: Vec<TaskId>, // 关闭时正在运行, 被中断
+    pub skipped: Vec<TaskId>,     // 上游任务没有成功, 被跳过
+    pub missed: Vec<TaskId>,      // 错过了截止时间
+}
+
+// 单个租户的统计, 由 Scheduler::tenant_stats 返回
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantStats {
+    pub tenant: String,
+    pub queued: usize,      // 就绪队列中的任务数
+    pub waiting: usize,     // 延迟, 等待重试或等待上游任务的任务数
+    pub consumed: Duration, // 累计运行时间
+}
+
+// 所有调度器句柄共享的数据
+struct Shared {
+    state: Mutex<SchedulerState>,
+    task_available: Condvar, // 有新任务或调度器停止时唤醒工作线程
+    idle: Condvar,           // 调度器空闲时唤醒 wait_idle
+    space_available: Condvar, // 排队任务减少或调度器关闭时唤醒等待空位的提交者
+    handles: Mutex<Vec<JoinHandle<()>>>, // 工作线程句柄, 为空表示还没有启动
+}
+
+impl Shared {
+    fn notify_if_idle(&self, state: &SchedulerState) {
+        if state.is_idle() {
+            self.idle.notify_all();
+        }
+    }
+}
+
+// 调度器句柄, clone 之后指向同一个调度器, 可以在任意线程提交任务
+// Arc: 可以多线程共享  Box: 一个指向堆分配内存的指针
+// Mutex: 互斥锁 Condvar: 条件变量, 让线程睡眠直到被唤醒
+#[derive(Clone)]
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    workers: usize,            // 工作线程数
+    quantum: Option<Duration>, // RR 时间片, None 表示任务一次运行到底
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl Scheduler {
+    // 创建
+    pub fn new() -> Self {
+        Scheduler::with_workers(DEFAULT_WORKERS)
+    }
+
+    // 指定工作线程数创建, 至少保留一个线程
+    pub fn with_workers(workers: usize) -> Self {
+        Scheduler {
+            shared: Arc::new(Shared {
+                state: Mutex::new(SchedulerState {
+                    ready: HashMap::new(),
+                    policy: Box::new(PriorityPolicy::default()),
+                    delayed: BinaryHeap::new(),
+                    blocked: HashMap::new(),
+                    dependents: HashMap::new(),
+                    records: HashMap::new(),
+                    recurring: HashMap::new(),
+                    timers: BinaryHeap::new(),
+                    aging: None,
+                    admission: None,
+                    capacity: None,
+                    next_aging: None,
+                    next_id: 0,
+                    next_recurring_id: 0,
+                    next_seq: 0,
+                    running: 0,
+                    queued_levels: HashMap::new(),
+                    consumed: HashMap::new(),
+                    shutdown: None,
+                }),
+                task_available: Condvar::new(),
+                idle: Condvar::new(),
+                space_available: Condvar::new(),
+                handles: Mutex::new(Vec::new()),
+            }),
+            workers: workers.max(1),
+            quantum: None,
+        }
+    }
+
+    // 开启 RR 时间片轮转: 任务每次最多运行 quantum (至少 1ms), 没运行完就回到同优先级队伍的末尾
+    pub fn round_robin(mut self, quantum: Duration) -> Self {
+        self.quantum = Some(quantum.max(Duration::from_millis(1)));
+        self
+    }
+
+    // 限制排队任务数 (至少为 1), 队列满时按 overflow 处理新提交的任务
+    // 重试, RR 重新入队和周期任务的实例不受限制
+    pub fn capacity(self, capacity: usize, overflow: OverflowPolicy) -> Self {
+        self.shared.state.lock().unwrap().capacity = Some((capacity.max(1), overflow));
+        self
+    }
+
+    // 开启优先级老化, 排队太久的任务逐级提升有效优先级
+    pub fn aging(self, policy: AgingPolicy) -> Self {
+        self.shared.state.lock().unwrap().aging = Some(policy);
+        self
+    }
+
+    // 设置准入策略, 提交时不满足的任务返回 TaskError::Rejected
+    pub fn admission(self, policy: AdmissionPolicy) -> Self {
+        self.shared.state.lock().unwrap().admission = Some(policy);
+        self
+    }
+
+    // 设置调度策略, 决定就绪任务的出队顺序, 默认为严格优先级 PriorityPolicy
+    // 已经在队列中的任务交给新的策略重新排序
+    pub fn policy(self, policy: impl SchedulingPolicy + 'static) -> Self {
+        let mut guard = self.shared.state.lock().unwrap();
+        let state = &mut *guard;
+        state.policy = Box::new(policy);
+        let mut ready: Vec<&QueuedTask> = state.ready.values().collect();
+        ready.sort_by_key(|queued| queued.seq);
+        for queued in ready {
+            state.policy.push(queued.info());
+        }
+        drop(guard);
+        self
+    }
+
+    // 开启 EDF (最早截止时间优先), 等同于 policy(EarliestDeadlineFirst::new())
+    pub fn earliest_deadline_first(self) -> Self {
+        self.policy(EarliestDeadlineFirst::new())
+    }
+
+    // 设置同优先级任务的出队顺序, 等同于 policy(PriorityPolicy::new(order))
+    pub fn queue_order(self, order: QueueOrder) -> Self {
+        self.policy(PriorityPolicy::new(order))
+    }
+
+    // 添加任务, 返回任务句柄; 调度器关闭后返回 ShutDown
+    pub fn add_task(&self, priority: Priority, task: Box<dyn Executable>) -> Result<TaskHandle, TaskError> {
+        self.add_task_with(priority, task, TaskOptions::default())
+    }
+
+    // 添加到指定时间才可以运行的任务, 等待期间状态为 Waiting
+    pub fn add_task_at(&self, priority: Priority, task: Box<dyn Executable>, at: Instant) -> Result<TaskHandle, TaskError> {
+        self.add_task_with(priority, task, TaskOptions::new().start_at(at))
+    }
+
+    // 添加延迟一段时间后才可以运行的任务
+    pub fn add_task_after(&self, priority: Priority, task: Box<dyn Executable>, delay: Duration) -> Result<TaskHandle, TaskError> {
+        self.add_task_at(priority, task, Instant::now() + delay)
+    }
+
+    // 添加带配置的任务 (例如超时时间, 依赖)
+    // 依赖的任务不存在时返回 NotFound
+    pub fn add_task_with(&self, priority: Priority, task: Box<dyn Executable>, options: TaskOptions) -> Result<TaskHandle, TaskError> {
+        self.submit_task(priority, task, options, true)
+    }
+
+    // 不阻塞的 add_task: 队列满时即使是 OverflowPolicy::Block 也直接返回 QueueFull
+    pub fn try_add_task(&self, priority: Priority, task: Box<dyn Executable>) -> Result<TaskHandle, TaskError> {
+        self.try_add_task_with(priority, task, TaskOptions::default())
+    }
+
+    // 不阻塞的 add_task_with
+    pub fn try_add_task_with(&self, priority: Priority, task: Box<dyn Executable>, options: TaskOptions) -> Result<TaskHandle, TaskError> {
+        self.submit_task(priority, task, options, false)
+    }
+
+    fn submit_task(&self, priority: Priority, task: Box<dyn Executable>, options: TaskOptions, blocking: bool) -> Result<TaskHandle, TaskError> {
+        let state = self.shared.state.lock().unwrap();
+        let submission = Submission::new(priority, task.as_ref(), &options);
+        let mut state = self.make_room(state, &[priority], &options.dependencies, blocking, |state| {
+            Scheduler::check_submission(state, options.dependencies.iter())?;
+            state.check_admission(&submission, &[])?;
+            self.check_deadline(state, task.as_ref(), &options)
+        })?;
+        let handle = state.submit(priority, Arc::from(task), options);
+        self.shared.task_available.notify_all();
+        Ok(handle)
+    }
+
+    // 提交一组有依赖关系的任务, 提交前检查依赖是否有环, 有环或依赖不存在时一个任务都不提交
+    // depends_on 用到了其他图的节点时返回 NotFound
+    // 返回的句柄与 TaskGraph::add 的顺序一致
+    pub fn add_graph(&self, graph: TaskGraph) -> Result<Vec<TaskHandle>, TaskError> {
+        if graph.invalid_edge {
+            return Err(TaskError::NotFound);
+        }
+        let order = graph.topological_order().ok_or(TaskError::DependencyCycle)?;
+
+        let state = self.shared.state.lock().unwrap();
+        let priorities: Vec<Priority> = graph.nodes.iter().map(|node| node.priority).collect();
+        let upstream: Vec<TaskId> = graph.nodes.iter().flat_map(|node| node.options.dependencies.iter().copied()).collect();
+        let mut state = self.make_room(state, &priorities, &upstream, true, |state| {
+            let external = graph.nodes.iter().flat_map(|node| node.options.dependencies.iter());
+            Scheduler::check_submission(state, external)?;
+            let mut admitted = Vec::new();
+            for node in &graph.nodes {
+                let submission = Submission::new(node.priority, node.task.as_ref(), &node.options);
+                state.check_admission(&submission, &admitted)?;
+                admitted.push(submission);
+                self.check_deadline(state, node.task.as_ref(), &node.options)?;
+            }
+            Ok(())
+        })?;
+
+        let mut nodes: Vec<Option<GraphNode>> = graph.nodes.into_iter().map(Some).collect();
+        let mut handles: Vec<Option<TaskHandle>> = (0..nodes.len()).map(|_| None).collect();
+        // 按拓扑顺序提交, 上游任务一定先拿到编号
+        for index in order {
+            let GraphNode { priority, task, mut options } = nodes[index].take().unwrap();
+            for &(from, to) in &graph.edges {
+                if to == index {
+                    options = options.depends_on(handles[from].as_ref().unwrap().id);
+                }
+            }
+            handles[index] = Some(state.submit(priority, Arc::from(task), options));
+        }
+        self.shared.task_available.notify_all();
+        Ok(handles.into_iter().map(Option::unwrap).collect())
+    }
+
+    // 设置了截止时间和预估运行时间的任务, 预计完成时间晚于截止时间时拒绝提交
+    // 调度策略按截止时间排序时 (EDF) 还要算上截止时间更早的排队任务, 它们会先运行, 按工作线程数平摊
+    fn check_deadline(&self, state: &SchedulerState, task: &dyn Executable, options: &TaskOptions) -> Result<(), TaskError> {
+        let (Some(deadline), Some(estimate)) = (options.deadline, options.estimate.or_else(|| task.estimated_duration())) else {
+            return Ok(());
+        };
+        let start = options.start_at.unwrap_or_else(Instant::now).max(Instant::now());
+        let ahead = if state.policy.orders_by_deadline() {
+            let total: Duration = state.ready.values()
+                .filter(|queued| queued.options.deadline.is_some_and(|other| other <= deadline))
+                .filter_map(QueuedTask::estimate)
+                .sum();
+            total / self.workers as u32
+        } else {
+            Duration::ZERO
+        };
+        if start + ahead + estimate > deadline {
+            return Err(TaskError::DeadlineUnreachable);
+        }
+        Ok(())
+    }
+
+    // 按队列容量为 incoming (要提交的任务的优先级) 腾出位置, 每次检查容量前先运行提交前的检查 check
+    // upstream 是新任务依赖的任务, EvictLowest 不会挤出它们和它们的上游
+    // Block 模式下 blocking 为 true 时在条件变量上等待空位, 等待期间释放锁,
+    // 醒来后重新运行 check: 等待期间依赖的任务可能被移除, 截止时间可能已经赶不上
+    fn make_room<'a>(
+        &self,
+        mut state: MutexGuard<'a, SchedulerState>,
+        incoming: &[Priority],
+        upstream: &[TaskId],
+        blocking: bool,
+        check: impl Fn(&SchedulerState) -> Result<(), TaskError>,
+    ) -> Result<MutexGuard<'a, SchedulerState>, TaskError> {
+        check(&state)?;
+        let Some((capacity, overflow)) = state.capacity else {
+            return Ok(state);
+        };
+        if incoming.len() > capacity {
+            return Err(TaskError::QueueFull);
+        }
+        loop {
+            if state.queued_count() + incoming.len() <= capacity {
+                return Ok(state);
+            }
+            match overflow {
+                OverflowPolicy::Block if blocking => {
+                    state = self.shared.space_available.wait(state).unwrap();
+                    check(&state)?;
+                }
+                OverflowPolicy::Block | OverflowPolicy::FailFast => return Err(TaskError::QueueFull),
+                OverflowPolicy::EvictLowest => {
+                    // 优先级最低的排队任务, 同优先级时挤出最后提交的; 不挤出新任务依赖的任务, 否则新任务会被跳过
+                    let lowest_incoming = incoming.iter().min().copied().unwrap_or(Priority::Background);
+                    let protected = state.pending_ancestors(upstream);
+                    let victim = state.queued_tasks()
+                        .filter(|queued| queued.priority < lowest_incoming && !protected.contains(&queued.id))
+                        .min_by_key(|queued| (queued.priority, cmp::Reverse(queued.id)))
+                        .map(|queued| (queued.id, queued.task.get_name()));
+                    let Some((victim, name)) = victim else {
+                        return Err(TaskError::QueueFull);
+                    };
+                    println!("{}队列已满, 挤出任务:{} {} {}", COLOR_YELLOW, COLOR_REST, victim, name);
+                    state.dequeue(victim);
+                    state.settle(victim, Err(TaskError::Evicted));
+                }
+            }
+        }
+    }
+
+    // 提交前的检查: 调度器没有关闭, 依赖的任务都存在
+    fn check_submission<'a>(state: &SchedulerState, mut dependencies: impl Iterator<Item = &'a TaskId>) -> Result<(), TaskError> {
+        if state.shutdown.is_some() {
+            return Err(TaskError::ShutDown);
+        }
+        if dependencies.any(|upstream| !state.records.contains_key(upstream)) {
+            return Err(TaskError::NotFound);
+        }
+        Ok(())
+    }
+
+    // 注册周期任务, 每次触发时用 factory 创建新的任务实例, 上一次运行还没结束时跳过本次触发
+    // 周期任务不计入 wait_idle, 关闭调度器后不再触发
+    pub fn add_recurring<F>(&self, priority: Priority, schedule: Schedule, factory: F) -> Result<RecurringId, TaskError>
+    where
+        F: Fn() -> Box<dyn Executable> + Send + Sync + 'static,
+    {
+        self.add_recurring_with(priority, schedule, OverlapPolicy::default(), TaskOptions::default(), factory)
+    }
+
+    // 注册周期任务, 指定重叠时的处理方式和每个实例的配置
+    // deadline 和 start_at 是绝对时间, 所有实例共用会在第一次触发后全部过期, 设置了时返回 Rejected
+    pub fn add_recurring_with<F>(&self, priority: Priority, schedule: Schedule, overlap: OverlapPolicy, options: TaskOptions, factory: F) -> Result<RecurringId, TaskError>
+    where
+        F: Fn() -> Box<dyn Executable> + Send + Sync + 'static,
+    {
+        if options.deadline.is_some() || options.start_at.is_some() {
+            return Err(TaskError::Rejected("周期任务的实例不支持绝对的 deadline / start_at".to_string()));
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        Scheduler::check_submission(&state, options.dependencies.iter())?;
+        let id = RecurringId(state.next_recurring_id);
+        state.next_recurring_id += 1;
+
+        let next_fire = schedule.next_after(SystemTime::now());
+        state.recurring.insert(id, RecurringTask {
+            priority,
+            factory: Arc::new(factory),
+            options,
+            schedule,
+            overlap,
+            next_fire: None,
+            runs: Vec::new(),
+            backlog: 0,
+        });
+        state.set_next_fire(id, next_fire);
+        // 工作线程可能正睡到更晚的时间, 唤醒它们重新计算
+        self.shared.task_available.notify_all();
+        Ok(id)
+    }
+
+    // 周期任务的下一次触发时间, 不会再触发时为 None
+    pub fn next_fire_time(&self, id: RecurringId) -> Result<Option<SystemTime>, TaskError> {
+        let state = self.shared.state.lock().unwrap();
+        state.recurring.get(&id).map(|recurring| recurring.next_fire).ok_or(TaskError::NotFound)
+    }
+
+    // 周期任务还没结束的任务实例, 按创建顺序; 结束的实例可以通过 TaskHandle 或 status 查询
+    pub fn recurring_runs(&self, id: RecurringId) -> Result<Vec<TaskId>, TaskError> {
+        let state = self.shared.state.lock().unwrap();
+        state.recurring.get(&id).map(|recurring| recurring.runs.clone()).ok_or(TaskError::NotFound)
+    }
+
+    // 停止周期任务, 之后不再触发; 已经创建的实例不受影响, 可以通过 cancel 取消
+    pub fn cancel_recurring(&self, id: RecurringId) -> Result<(), TaskError> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.recurring.remove(&id).map(|_| ()).ok_or(TaskError::NotFound)
+    }
+
+    // 查询任务状态, 排队中的任务带上查询时的有效优先级
+    pub fn status(&self, id: TaskId) -> Result<TaskStatus, TaskError> {
+        let state = self.shared.state.lock().unwrap();
+        state.status(id).ok_or(TaskError::NotFound)
+    }
+
+    // 调整还没运行的任务 (排队中, 等待到期或等待上游任务) 的优先级, 排队中的任务立即按新优先级调整位置
+    // 任务不存在, 正在运行或已经结束时返回 NotFound
+    pub fn set_priority(&self, id: TaskId, priority: Priority) -> Result<(), TaskError> {
+        let mut guard = self.shared.state.lock().unwrap();
+        let state = &mut *guard;
+        let record = state.records.get_mut(&id).ok_or(TaskError::NotFound)?;
+        let old = record.priority;
+        match record.status {
+            TaskStatus::Queued { .. } => {
+                record.priority = priority;
+                // 从调度策略中移除后按新优先级重新入队
+                if let Some(mut task) = state.remove_ready(id) {
+                    // 老化从调整时重新开始计算
+                    task.priority = priority;
+                    state.enqueue(task);
+                }
+            }
+            TaskStatus::Waiting => {
+                record.priority = priority;
+                let mut delayed = std::mem::take(&mut state.delayed).into_vec();
+                for entry in delayed.iter_mut().filter(|entry| entry.task.id == id) {
+                    entry.task.priority = priority;
+                }
+                state.delayed = BinaryHeap::from(delayed);
+                state.track_queued(old, false);
+                state.track_queued(priority, true);
+            }
+            TaskStatus::Blocked => {
+                record.priority = priority;
+                if let Some(blocked) = state.blocked.get_mut(&id) {
+                    blocked.task.priority = priority;
+                    state.track_queued(old, false);
+                    state.track_queued(priority, true);
+                }
+            }
+            _ => return Err(TaskError::NotFound),
+        }
+        println!("{}调整优先级:{} {} {:?} -> {:?}", COLOR_YELLOW, COLOR_REST, id, old, priority);
+        Ok(())
+    }
+
+    // 查询任务的有效优先级: 排队中的任务包含老化的提升, 其他状态为提交时的优先级
+    pub fn effective_priority(&self, id: TaskId) -> Result<Priority, TaskError> {
+        let state = self.shared.state.lock().unwrap();
+        if !state.records.contains_key(&id) {
+            return Err(TaskError::NotFound);
+        }
+        Ok(state.effective_priority(id))
+    }
+
+    // 按租户统计队列深度和累计运行时间, 按租户名排序
+    pub fn tenant_stats(&self) -> Vec<TenantStats> {
+        let state = self.shared.state.lock().unwrap();
+        fn entry<'a>(stats: &'a mut BTreeMap<String, TenantStats>, tenant: &str) -> &'a mut TenantStats {
+            stats.entry(tenant.to_string()).or_insert_with(|| TenantStats { tenant: tenant.to_string(), ..TenantStats::default() })
+        }
+        let mut stats = BTreeMap::new();
+        for queued in state.ready.values() {
+            entry(&mut stats, queued.options.tenant_name()).queued += 1;
+        }
+        let waiting = state.delayed.iter().map(|delayed| &delayed.task).chain(state.blocked.values().map(|blocked| &blocked.task));
+        for queued in waiting {
+            entry(&mut stats, queued.options.tenant_name()).waiting += 1;
+        }
+        for (tenant, consumed) in &state.consumed {
+            entry(&mut stats, tenant).consumed = *consumed;
+        }
+        stats.into_values().collect()
+    }
+
+    // 查询运行成功的任务的输出值, 其他线程或任务可以借此读取上游的结果
+    // 任务不存在或还没有成功结束时返回 NotFound
+    pub fn output(&self, id: TaskId) -> Result<TaskOutput, TaskError> {
+        let state = self.shared.state.lock().unwrap();
+        state.records.get(&id).and_then(|record| record.output.clone()).ok_or(TaskError::NotFound)
+    }
+
+    // 按具体类型查询任务的输出值
+    pub fn output_as<T: Any + Send + Sync>(&self, id: TaskId) -> Result<Arc<T>, TaskError> {
+        downcast_output(self.output(id)?)
+    }
+
+    // 取消任务: 排队中的任务直接出队, 运行中的任务收到取消通知, 结束后状态为 Cancelled
+    // 已经结束的任务不受影响
+    pub fn cancel(&self, id: TaskId) -> Result<(), TaskError> {
+        let mut state = self.shared.state.lock().unwrap();
+        let status = state.status(id).ok_or(TaskError::NotFound)?;
+        match status {
+            TaskStatus::Queued { .. } | TaskStatus::Waiting | TaskStatus::Blocked => {
+                state.dequeue(id);
+                state.settle(id, Err(TaskError::Cancelled));
+                self.shared.notify_if_idle(&state);
+                self.shared.space_available.notify_all();
+            }
+            TaskStatus::Running => {
+                let record = state.records.get_mut(&id).unwrap();
+                record.cancel_requested = true;
+                record.ctx.cancel();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // 从调度器中彻底移除任务并返回移除前的状态, 之后再查询会得到 NotFound
+    // 排队中的任务出队, 运行中的任务收到取消通知, 两者的任务句柄都会得到 Cancelled, 下游任务被跳过
+    pub fn remove(&self, id: TaskId) -> Result<TaskStatus, TaskError> {
+        let mut state = self.shared.state.lock().unwrap();
+        let status = state.status(id).ok_or(TaskError::NotFound)?;
+        match status {
+            TaskStatus::Queued { .. } | TaskStatus::Waiting | TaskStatus::Blocked | TaskStatus::Running => {
+                state.dequeue(id);
+                state.records[&id].ctx.cancel();
+                state.settle(id, Err(TaskError::Cancelled));
+                self.shared.space_available.notify_all();
+            }
+            _ => {}
+        }
+        state.records.remove(&id);
+        self.shared.notify_if_idle(&state);
+        Ok(status)
+    }
+
+    // 时间片用完的任务重新入队, 分配新的 seq 排到同优先级任务的后面, 让同级其他任务先运行
+    fn requeue(state: &mut SchedulerState, mut task: QueuedTask) {
+        task.seq = state.next_seq;
+        task.requeued = true;
+        state.next_seq += 1;
+        state.enqueue(task);
+    }
+
+    // 启动工作线程, 之后提交的任务会被立即处理; 已经启动时不做任何事
+    // 队列为空时工作线程在条件变量上睡眠, 不会退出
+    pub fn start(&self) {
+        let mut handles = self.shared.handles.lock().unwrap();
+        if !handles.is_empty() {
+            return;
+        }
+
+        println!("--- 调度器开始工作, 工作线程数: {}, 待处理任务总数: {}", self.workers, self.shared.state.lock().unwrap().ready.len());
+        if let Some(quantum) = self.quantum {
+            println!("--- RR 模式, 时间片: {:?}", quantum);
+        }
+        println!();
+
+        for worker_id in 0..self.workers {
+            // 每个工作线程持有一份共享数据的 Arc
+            let shared = Arc::clone(&self.shared);
+            let quantum = self.quantum;
+            handles.push(thread::spawn(move || Scheduler::worker_loop(&shared, worker_id, quantum)));
+        }
+    }
+
+    // 阻塞直到队列为空且没有运行中的任务
+    pub fn wait_idle(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        while !state.is_idle() {
+            state = self.shared.idle.wait(state).unwrap();
+        }
+    }
+
+    // 运行所有任务直到调度器空闲, 然后关闭调度器
+    // 运行期间其他句柄仍然可以提交任务, 这些任务也会被处理完
+    pub fn run_all(self) {
+        self.start();
+        self.wait_idle();
+        let summary = self.shutdown(ShutdownMode::Drain);
+
+        println!("--- 所有任务执行完毕, 成功: {}, 失败: {} ---", summary.completed.len(), summary.failed.len());
+    }
+
+    // 关闭调度器, 之后提交任务会返回 ShutDown; 等待工作线程退出后返回任务汇总
+    // Immediate 模式只是通知任务取消, 任务需要通过 TaskContext 配合才能尽快结束
+    pub fn shutdown(&self, mode: ShutdownMode) -> ShutdownSummary {
+        let mut summary = ShutdownSummary::default();
+        let in_flight: Vec<TaskId> = {
+            let mut guard = self.shared.state.lock().unwrap();
+            let state = &mut *guard;
+            state.shutdown = Some(mode);
+
+            // 周期任务不再触发, 已经创建的实例按关闭方式处理
+            state.timers.clear();
+            for recurring in state.recurring.values_mut() {
+                recurring.next_fire = None;
+                recurring.backlog = 0;
+            }
+
+            if mode != ShutdownMode::Drain {
+                let waiting = state.delayed.drain().map(|delayed| delayed.task);
+                let blocked = state.blocked.drain().map(|(_, blocked)| blocked.task);
+                while state.policy.pop().is_some() {}
+                let ready = state.ready.drain().map(|(_, queued)| queued);
+                let dropped: Vec<QueuedTask> = ready.chain(waiting).chain(blocked).collect();
+                state.queued_levels.clear();
+                for queued in dropped {
+                    if let Some(record) = state.records.get_mut(&queued.id) {
+                        record.finish(Err(TaskError::Cancelled));
+                    }
+                    state.policy.on_settled(queued.id);
+                    summary.dropped.push(queued.id);
+                }
+                // 被丢弃的周期任务实例同样算作结束
+                for recurring in state.recurring.values_mut() {
+                    recurring.runs.retain(|run| !summary.dropped.contains(run));
+                }
+            }
+
+            let running: Vec<TaskId> = state.records.iter()
+                .filter(|(_, record)| record.status == TaskStatus::Running)
+                .map(|(id, _)| *id)
+                .collect();
+            if mode == ShutdownMode::Immediate {
+                for id in &running {
+                    let record = state.records.get_mut(id).unwrap();
+                    record.cancel_requested = true;
+                    record.ctx.cancel();
+                }
+            }
+            self.shared.notify_if_idle(state);
+            running
+        };
+
+        // Drain 需要把排队中的任务运行完, 还没启动时先启动工作线程
+        if mode == ShutdownMode::Drain {
+            self.start();
+        }
+        self.shared.task_available.notify_all();
+        self.shared.space_available.notify_all();
+        // 等待所有工作线程结束
+        let handles = std::mem::take(&mut *self.shared.handles.lock().unwrap());
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let state = self.shared.state.lock().unwrap();
+        let mut ids: Vec<&TaskId> = state.records.keys().collect();
+        ids.sort();
+        for id in ids {
+            match state.records[id].status {
+                TaskStatus::Completed => summary.completed.push(*id),
+                TaskStatus::Failed(_) => summary.failed.push(*id),
+                TaskStatus::Cancelled if in_flight.contains(id) => summary.interrupted.push(*id),
+                TaskStatus::Skipped => summary.skipped.push(*id),
+                TaskStatus::DeadlineMissed => summary.missed.push(*id),
+                _ => {}
+            }
+        }
+        summary.dropped.sort();
+        summary
+    }
+
+    // 工作线程: 不断取出优先级最高的任务运行, 队列为空时睡眠等待新任务
+    fn worker_loop(shared: &Shared, worker_id: usize, quantum: Option<Duration>) {
+        loop {
+            // 只在取任务时持有锁, 任务运行期间其他线程可以继续取任务
+            let (mut queued, ctx, quantum) = {
+                let mut state = shared.state.lock().unwrap();
+                let queued = loop {
+                    state.promote_due();
+                    if let Some(queued) = state.pop_ready() {
+                        shared.space_available.notify_all();
+                        // 已经过了截止时间的任务不再运行
+                        if queued.options.deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                            println!("{}错过截止时间, 不再运行:{} {} {}", COLOR_RED, COLOR_REST, queued.id, queued.task.get_name());
+                            state.policy.on_ran(&queued.info(), Duration::ZERO);
+                            state.settle(queued.id, Err(TaskError::DeadlineMissed));
+                            shared.notify_if_idle(&state);
+                            continue;
+                        }
+                        break queued;
+                    }
+                    if state.shutdown.is_some() && state.delayed.is_empty() && state.blocked.is_empty() {
+                        return;
+                    }
+                    // wait 会释放锁并睡眠, 被唤醒后重新获取锁
+                    // 有等待中的任务时最多睡到最早的任务到期, 新提交的任务会唤醒线程重新计算到期时间
+                    state = match state.next_wakeup() {
+                        Some(due) => {
+                            let timeout = due.saturating_duration_since(Instant::now());
+                            shared.task_available.wait_timeout(state, timeout).unwrap().0
+                        }
+                        None => shared.task_available.wait(state).unwrap(),
+                    };
+                };
+                state.running += 1;
+                let record = state.records.get_mut(&queued.id).expect("排队中的任务必须有记录");
+                record.status = TaskStatus::Running;
+                if queued.ran_for.is_zero() {
+                    record.slot.attempts.store(queued.attempt, Ordering::SeqCst);
+                }
+                let ctx = record.ctx.clone();
+                // 调度策略可以为每个任务指定时间片, 否则使用 round_robin 的设置
+                let quantum = state.policy.time_slice(&queued.info()).or(quantum);
+                (queued, ctx, quantum)
+            };
+            let task = Arc::clone(&queued.task);
+
+            let attempt = if queued.attempt > 1 { format!(" (第 {} 次尝试)", queued.attempt) } else { String::new() };
+            // 老化提升过的任务同时显示原优先级
+            let aged = if queued.effective != queued.priority { format!(" (原 {:?})", queued.priority) } else { String::new() };
+            println!("{}[{:?}]{}{} 工作线程 #{} 准备运行: {} {}{}",  match queued.effective {
+               p if p >= Priority::High => COLOR_RED, 
+               p if p >= Priority::Medium => COLOR_YELLOW, 
+               _ => COLOR_GREEN, 
+            },  queued.effective, COLOR_REST, aged, worker_id, queued.id, task.get_name(), attempt);
+
+            // 超时按累计运行时间计算, RR 模式下每个时间片只能用剩余的部分
+            // 有截止时间时最多运行到截止时间
+            let timeout = queued.options.timeout.map(|t| t.saturating_sub(queued.ran_for));
+            let until_deadline = queued.options.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let timeout = match (timeout, until_deadline) {
+                (Some(timeout), Some(until_deadline)) => Some(timeout.min(until_deadline)),
+                (timeout, until_deadline) => timeout.or(until_deadline),
+            };
+            let started = Instant::now();
+            let mut result = Scheduler::execute_with_watchdog(&task, &ctx, quantum, timeout);
+            queued.ran_for += started.elapsed();
+            if matches!(result, Err(TaskError::TimeOut)) && queued.options.deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                result = Err(TaskError::DeadlineMissed);
+            }
+
+            let mut guard = shared.state.lock().unwrap();
+            let state = &mut *guard;
+            state.running -= 1;
+            let elapsed = started.elapsed();
+            *state.consumed.entry(queued.options.tenant_name().to_string()).or_default() += elapsed;
+            state.policy.on_ran(&queued.info(), elapsed);
+
+            // 运行期间任务被 remove 了, 不再记录结果
+            let id = queued.id;
+            let mut settled = None;
+            if let Some(record) = state.records.get_mut(&id) {
+                if record.cancel_requested {
+                    settled = Some(Err(TaskError::Cancelled));
+                    println!("{}Cancelled: {}{}", COLOR_YELLOW, COLOR_REST, task.get_name());
+                } else {
+                    match result {
+                        Ok(SliceOutcome::Finished) => {
+                            state.policy.on_finished(&queued.info());
+                            settled = Some(Ok(ctx.take_output()));
+                            println!("{}Successfully Finished: {}{}", COLOR_GREEN, COLOR_REST, task.get_name());
+                        }
+                        // 除了 Drain 以外的关闭方式不再运行排队任务, 没运行完的任务算作被中断
+                        Ok(SliceOutcome::Yielded) if matches!(state.shutdown, Some(mode) if mode != ShutdownMode::Drain) => {
+                            settled = Some(Err(TaskError::Cancelled));
+                            println!("{}调度器关闭, 任务被中断: {}{} (已运行 {:?})", COLOR_YELLOW, COLOR_REST, task.get_name(), queued.ran_for);
+                        }
+                        Ok(SliceOutcome::Yielded) => {
+                            record.status = TaskStatus::Queued { effective: record.priority };
+                            println!("{}时间片用完, 重新入队: {}{} (已运行 {:?})", COLOR_YELLOW, COLOR_REST, task.get_name(), queued.ran_for);
+                            Scheduler::requeue(state, queued);
+                        }
+                        Err(e) => {
+                            eprintln!("{}Error running :{} {} {}", COLOR_RED, COLOR_REST, task.get_name(), e);
+                            // 关闭调度器时 (Drain 除外) 不再重试
+                            let retry_delay = queued.options.retry.as_ref()
+                                .filter(|_| e.is_retryable())
+                                .filter(|_| matches!(state.shutdown, None | Some(ShutdownMode::Drain)))
+                                .and_then(|policy| policy.delay_after(queued.attempt));
+                            match retry_delay {
+                                Some(delay) => {
+                                    println!("{}第 {} 次尝试失败, {:?} 后重试: {}{}", COLOR_YELLOW, queued.attempt, delay, COLOR_REST, task.get_name());
+                                    // 上一次尝试可能因为超时被取消, 重试使用新的上下文
+                                    record.ctx = record.ctx.renew();
+                                    queued.attempt += 1;
+                                    queued.ran_for = Duration::ZERO;
+                                    state.push_delayed(queued, Instant::now() + delay);
+                                    shared.task_available.notify_one();
+                                }
+                                None => settled = Some(Err(e)),
+                            }
+                        }
+                    }
+                }
+            }
+            // 任务结束可能释放了下游任务
+            if let Some(result) = settled {
+                state.settle(id, result);
+                if !state.ready.is_empty() {
+                    shared.task_available.notify_all();
+                }
+                // 被跳过的下游任务离开了队列
+                shared.space_available.notify_all();
+            }
+            shared.notify_if_idle(state);
+            drop(guard);
+            println!();
+        }
+    }
+
+    // 运行任务, 有时间片时只运行一个时间片
+    // 设置了超时时间时由当前工作线程充当看门狗:
+    // 任务放到单独的线程中运行, 超时后通知任务取消并返回 TimeOut, 工作线程继续处理下一个任务
+    fn execute_with_watchdog(
+        task: &Arc<dyn Executable>,
+        ctx: &TaskContext,
+        quantum: Option<Duration>,
+        timeout: Option<Duration>,
+    ) -> Result<SliceOutcome, TaskError> {
+        // 任务 panic 时按致命错误结束, 不论是否设置了超时, 工作线程都不会因此退出
+        let run = move |task: &dyn Executable, ctx: &TaskContext| {
+            panic::catch_unwind(AssertUnwindSafe(|| match quantum {
+                Some(quantum) => task.run_slice(ctx, quantum),
+                None => task.execute(ctx).map(|_| SliceOutcome::Finished),
+            }))
+            .unwrap_or_else(|_| Err(TaskError::fatal("任务 panic")))
+        };
+
+        let Some(timeout) = timeout else {
+            return run(task.as_ref(), ctx);
+        };
+        if timeout.is_zero() {
+            return Err(TaskError::TimeOut);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let runner_task = Arc::clone(task);
+        let runner_ctx = ctx.clone();
+        thread::spawn(move || {
+            // 超时后接收端已被丢弃, 发送失败可以忽略
+            let _ = tx.send(run(runner_task.as_ref(), &runner_ctx));
+        });
+
+        match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                ctx.cancel();
+                Err(TaskError::TimeOut)
+            }
+            // 发送端在没有发送结果的情况下被丢弃, 说明任务线程异常退出了
+            Err(RecvTimeoutError::Disconnected) => Err(TaskError::fatal("任务线程异常退出")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 运行指定毫秒数的测试任务
+    struct SleepTask(&'static str, u64);
+
+    impl Executable for SleepTask {
+        fn execute(&self, ctx: &TaskContext) -> Result<(), TaskError> {
+            ctx.sleep(Duration::from_millis(self.1))
+        }
+
+        fn get_name(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    // 直接失败的测试任务
+    struct FailTask;
+
+    impl Executable for FailTask {
+        fn execute(&self, _ctx: &TaskContext) -> Result<(), TaskError> {
+            Err(TaskError::fatal("失败"))
+        }
+
+        fn get_name(&self) -> String {
+            "fail".to_string()
+        }
+    }
+
+    // 记录同时运行的任务数的峰值
+    struct ProbeTask {
+        running: Arc<AtomicU32>,
+        peak: Arc<AtomicU32>,
+    }
+
+    impl Executable for ProbeTask {
+        fn execute(&self, ctx: &TaskContext) -> Result<(), TaskError> {
+            let now = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            let result = ctx.sleep(Duration::from_millis(100));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+
+        fn get_name(&self) -> String {
+            "probe".to_string()
+        }
+    }
+
+    #[test]
+    fn graph_with_cycle_is_rejected() {
+        let scheduler = Scheduler::new();
+        let mut graph = TaskGraph::new();
+        let a = graph.add(Priority::High, Box::new(SleepTask("a", 10)), TaskOptions::new());
+        let b = graph.add(Priority::High, Box::new(SleepTask("b", 10)), TaskOptions::new());
+        graph.depends_on(b, a).depends_on(a, b);
+        assert!(matches!(scheduler.add_graph(graph), Err(TaskError::DependencyCycle)));
+        assert!(matches!(scheduler.status(TaskId(0)), Err(TaskError::NotFound)));
+    }
+
+    #[test]
+    fn graph_rejects_node_of_another_graph() {
+        let scheduler = Scheduler::new();
+        let mut other = TaskGraph::new();
+        let foreign = other.add(Priority::High, Box::new(SleepTask("foreign", 10)), TaskOptions::new());
+        other.add(Priority::High, Box::new(SleepTask("foreign", 10)), TaskOptions::new());
+        let mut graph = TaskGraph::new();
+        let a = graph.add(Priority::High, Box::new(SleepTask("a", 10)), TaskOptions::new());
+        graph.depends_on(a, foreign);
+        assert!(matches!(scheduler.add_graph(graph), Err(TaskError::NotFound)));
+    }
+
+    #[test]
+    fn graph_skips_dependents_of_failed_node() {
+        let scheduler = Scheduler::with_workers(2);
+        let mut graph = TaskGraph::new();
+        let failing = graph.add(Priority::High, Box::new(FailTask), TaskOptions::new());
+        let child = graph.add(Priority::High, Box::new(SleepTask("child", 10)), TaskOptions::new());
+        let grandchild = graph.add(Priority::High, Box::new(SleepTask("grandchild", 10)), TaskOptions::new());
+        graph.depends_on(child, failing).depends_on(grandchild, child);
+        let handles = scheduler.add_graph(graph).unwrap();
+        let ids: Vec<TaskId> = handles.iter().map(TaskHandle::id).collect();
+        scheduler.clone().run_all();
+        assert!(matches!(scheduler.status(ids[0]), Ok(TaskStatus::Failed(_))));
+        assert_eq!(scheduler.status(ids[1]).unwrap(), TaskStatus::Skipped);
+        assert_eq!(scheduler.status(ids[2]).unwrap(), TaskStatus::Skipped);
+        // 被跳过的任务离开阻塞队列时同样更新排队计数
+        assert!(scheduler.shared.state.lock().unwrap().queued_levels.values().all(|count| *count == 0));
+    }
+
+    #[test]
+    fn graph_runs_siblings_in_parallel_after_upstream() {
+        let scheduler = Scheduler::with_workers(2);
+        let (running, peak) = (Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0)));
+        let probe = || Box::new(ProbeTask { running: Arc::clone(&running), peak: Arc::clone(&peak) });
+        let mut graph = TaskGraph::new();
+        let join = graph.add(Priority::High, Box::new(SleepTask("join", 10)), TaskOptions::new());
+        let left = graph.add(Priority::High, probe(), TaskOptions::new());
+        let right = graph.add(Priority::High, probe(), TaskOptions::new());
+        let root = graph.add(Priority::High, Box::new(SleepTask("root", 10)), TaskOptions::new());
+        graph.depends_on(left, root).depends_on(right, root).depends_on(join, left).depends_on(join, right);
+        let handles = scheduler.add_graph(graph).unwrap();
+        let ids: Vec<TaskId> = handles.iter().map(TaskHandle::id).collect();
+        // 按拓扑顺序提交, 上游任务的编号更小
+        assert!(ids[3] < ids[1] && ids[3] < ids[2] && ids[1] < ids[0] && ids[2] < ids[0]);
+        scheduler.clone().run_all();
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert!(ids.iter().all(|id| scheduler.status(*id).unwrap() == TaskStatus::Completed));
+    }
+
+    #[test]
+    fn deadline_check_uses_declared_estimate() {
+        let scheduler = Scheduler::new();
+        let options = TaskOptions::new().deadline(Instant::now() + Duration::from_secs(1));
+        let result = scheduler.add_task_with(Priority::High, Box::new(SimpleTask::new("long".to_string(), 10)), options);
+        assert!(matches!(result, Err(TaskError::DeadlineUnreachable)));
+    }
+
+    #[test]
+    fn dependent_of_deadline_missed_upstream_is_skipped() {
+        let scheduler = Scheduler::with_workers(1);
+        scheduler.start();
+        let options = TaskOptions::new().deadline(Instant::now());
+        let upstream = scheduler.add_task_with(Priority::High, Box::new(SleepTask("upstream", 10)), options).unwrap();
+        let upstream_id = upstream.id();
+        assert!(matches!(upstream.join(), Err(TaskError::DeadlineMissed)));
+
+        let options = TaskOptions::new().depends_on(upstream_id);
+        let dependent = scheduler.add_task_with(Priority::High, Box::new(SleepTask("dependent", 10)), options).unwrap();
+        let dependent_id = dependent.id();
+        assert!(matches!(dependent.join_timeout(Duration::from_secs(1)), Some(Err(TaskError::DependencyFailed(id))) if id == upstream_id));
+        assert_eq!(scheduler.status(dependent_id).unwrap(), TaskStatus::Skipped);
+        scheduler.wait_idle();
+        scheduler.shutdown(ShutdownMode::Drain);
+    }
+
+    #[test]
+    fn recurring_instances_go_through_admission() {
+        let scheduler = Scheduler::with_workers(1).admission(AdmissionPolicy::new().max_estimate(Duration::from_secs(1)));
+        scheduler.start();
+        let options = TaskOptions::new().estimate(Duration::from_secs(10));
+        let schedule = Schedule::every(Duration::from_millis(20)).unwrap();
+        let id = scheduler
+            .add_recurring_with(Priority::High, schedule, OverlapPolicy::default(), options, || Box::new(SleepTask("tick", 1)))
+            .unwrap();
+        thread::sleep(Duration::from_millis(150));
+        assert!(scheduler.recurring_runs(id).unwrap().is_empty());
+        scheduler.shutdown(ShutdownMode::Drain);
+    }
+
+    #[test]
+    fn blocked_submission_rechecks_dependencies_after_waiting() {
+        let scheduler = Scheduler::with_workers(1).capacity(1, OverflowPolicy::Block);
+        scheduler.start();
+        let upstream = scheduler.add_task(Priority::High, Box::new(SleepTask("upstream", 1))).unwrap();
+        let upstream_id = upstream.id();
+        upstream.join().unwrap();
+        // 一个任务占住工作线程, 另一个占满队列
+        scheduler.add_task(Priority::High, Box::new(SleepTask("running", 200))).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        scheduler.add_task(Priority::High, Box::new(SleepTask("queued", 1))).unwrap();
+
+        let submitter = scheduler.clone();
+        let blocked = thread::spawn(move || {
+            let options = TaskOptions::new().depends_on(upstream_id);
+            submitter.add_task_with(Priority::High, Box::new(SleepTask("dependent", 1)), options).map(|handle| handle.id())
+        });
+        thread::sleep(Duration::from_millis(50));
+        scheduler.remove(upstream_id).unwrap();
+        assert!(matches!(blocked.join().unwrap(), Err(TaskError::NotFound)));
+        scheduler.wait_idle();
+        scheduler.shutdown(ShutdownMode::Drain);
+    }
+
+    #[test]
+    fn recurring_runs_keep_only_unfinished_instances() {
+        let scheduler = Scheduler::with_workers(2);
+        scheduler.start();
+        let schedule = Schedule::every(Duration::from_millis(30)).unwrap();
+        let fast = scheduler.add_recurring(Priority::High, schedule.clone(), || Box::new(SleepTask("fast", 1))).unwrap();
+        let slow = scheduler.add_recurring(Priority::High, schedule, || Box::new(SleepTask("slow", 500))).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        // 结束的实例不再保留
+        assert!(scheduler.recurring_runs(fast).unwrap().len() <= 1);
+        // 上一次运行还没结束, 后面的触发都被跳过
+        assert_eq!(scheduler.recurring_runs(slow).unwrap().len(), 1);
+        scheduler.shutdown(ShutdownMode::Immediate);
+    }
+
+    #[test]
+    fn status_shows_aged_priority() {
+        let scheduler = Scheduler::new().aging(AgingPolicy::new().promote_after(Priority::Low, Duration::from_millis(50)));
+        let handle = scheduler.add_task(Priority::Low, Box::new(SleepTask("low", 1))).unwrap();
+        assert_eq!(scheduler.status(handle.id()).unwrap(), TaskStatus::Queued { effective: Priority::Low });
+        thread::sleep(Duration::from_millis(80));
+        assert_eq!(scheduler.status(handle.id()).unwrap(), TaskStatus::Queued { effective: Priority::Medium });
+        assert_eq!(scheduler.effective_priority(handle.id()).unwrap(), Priority::Medium);
+    }
+
+    #[test]
+    fn round_robin_quantum_is_at_least_one_millisecond() {
+        assert_eq!(Scheduler::new().round_robin(Duration::ZERO).quantum, Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn simple_task_keeps_sub_millisecond_progress() {
+        let task = SimpleTask::new("slice".to_string(), 1);
+        let ctx = TaskContext::new();
+        for _ in 0..3 {
+            assert!(matches!(task.run_slice(&ctx, Duration::from_micros(500)), Ok(SliceOutcome::Yielded)));
+        }
+        assert_eq!(task.progress_ns.load(Ordering::SeqCst), 1_500_000);
+    }
+
+    // 运行时 panic 的测试任务
+    struct PanicTask;
+
+    impl Executable for PanicTask {
+        fn execute(&self, _ctx: &TaskContext) -> Result<(), TaskError> {
+            panic!("测试 panic");
+        }
+
+        fn get_name(&self) -> String {
+            "panic".to_string()
+        }
+    }
+
+    #[test]
+    fn panicking_task_fails_without_killing_the_worker() {
+        let scheduler = Scheduler::with_workers(1);
+        scheduler.start();
+        for options in [TaskOptions::new(), TaskOptions::new().timeout(Duration::from_secs(1))] {
+            let handle = scheduler.add_task_with(Priority::High, Box::new(PanicTask), options).unwrap();
+            let id = handle.id();
+            assert!(matches!(handle.join_timeout(Duration::from_secs(1)), Some(Err(TaskError::Failed(_)))));
+            assert!(matches!(scheduler.status(id), Ok(TaskStatus::Failed(_))));
+        }
+        // 唯一的工作线程仍然可以运行后面的任务
+        let handle = scheduler.add_task(Priority::High, Box::new(SleepTask("after", 1))).unwrap();
+        assert!(handle.join_timeout(Duration::from_secs(1)).is_some_and(|result| result.is_ok()));
+        scheduler.wait_idle();
+        scheduler.shutdown(ShutdownMode::Drain);
+    }
+
+    #[test]
+    fn admission_quota_follows_cancel_and_priority_changes() {
+        let scheduler = Scheduler::new().admission(AdmissionPolicy::new().quota(Priority::Low, 2).max_queued(3));
+        let low = || Box::new(SleepTask("low", 1));
+        let first = scheduler.add_task(Priority::Low, low()).unwrap();
+        let second = scheduler.add_task(Priority::Low, low()).unwrap();
+        assert!(matches!(scheduler.add_task(Priority::Low, low()), Err(TaskError::Rejected(_))));
+
+        scheduler.cancel(first.id()).unwrap();
+        scheduler.add_task(Priority::Low, low()).unwrap();
+        scheduler.set_priority(second.id(), Priority::High).unwrap();
+        scheduler.add_task(Priority::Low, low()).unwrap();
+        // 总数达到 max_queued
+        assert!(matches!(scheduler.add_task(Priority::High, low()), Err(TaskError::Rejected(_))));
+
+        let state = scheduler.shared.state.lock().unwrap();
+        for (level, count) in &state.queued_levels {
+            assert_eq!(*count, state.queued_tasks().filter(|queued| queued.priority.level() == *level).count());
+        }
+    }
+
+    #[test]
+    fn finished_recurring_instances_leave_no_records() {
+        let scheduler = Scheduler::with_workers(2);
+        scheduler.start();
+        let schedule = Schedule::every(Duration::from_millis(20)).unwrap();
+        let id = scheduler.add_recurring(Priority::High, schedule, || Box::new(SleepTask("tick", 1))).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        scheduler.cancel_recurring(id).unwrap();
+        scheduler.wait_idle();
+        assert!(scheduler.shared.state.lock().unwrap().records.is_empty());
+        scheduler.shutdown(ShutdownMode::Drain);
+    }
+
+    #[test]
+    fn recurring_rejects_absolute_deadline() {
+        let scheduler = Scheduler::new();
+        let schedule = Schedule::every(Duration::from_secs(1)).unwrap();
+        let options = TaskOptions::new().deadline(Instant::now() + Duration::from_secs(10));
+        let result = scheduler.add_recurring_with(Priority::High, schedule, OverlapPolicy::default(), options, || Box::new(SleepTask("tick", 1)));
+        assert!(matches!(result, Err(TaskError::Rejected(_))));
+    }
+
+    #[test]
+    fn eviction_spares_upstream_of_incoming_task() {
+        let scheduler = Scheduler::new().capacity(2, OverflowPolicy::EvictLowest);
+        let root = scheduler.add_task(Priority::Background, Box::new(SleepTask("root", 1))).unwrap();
+        let options = TaskOptions::new().depends_on(root.id());
+        let upstream = scheduler.add_task_with(Priority::Low, Box::new(SleepTask("upstream", 1)), options).unwrap();
+
+        // 队列里只有新任务的上游, 没有可以挤出的任务
+        let options = TaskOptions::new().depends_on(upstream.id());
+        let result = scheduler.add_task_with(Priority::High, Box::new(SleepTask("dependent", 1)), options);
+        assert!(matches!(result, Err(TaskError::QueueFull)));
+        assert!(matches!(scheduler.status(root.id()), Ok(TaskStatus::Queued { .. })));
+        assert_eq!(scheduler.status(upstream.id()).unwrap(), TaskStatus::Blocked);
+
+        // 没有依赖的新任务照常挤出优先级最低的任务
+        scheduler.add_task(Priority::High, Box::new(SleepTask("other", 1))).unwrap();
+        assert_eq!(scheduler.status(root.id()).unwrap(), TaskStatus::Cancelled);
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially_until_attempts_run_out() {
+        let policy = RetryPolicy::new(4).base_delay(Duration::from_millis(100));
+        let delays: Vec<Option<Duration>> = (1..=4).map(|attempt| policy.delay_after(attempt)).collect();
+        let ms = |ms| Some(Duration::from_millis(ms));
+        assert_eq!(delays, vec![ms(100), ms(200), ms(400), None]);
+        // 至少尝试一次, 第一次失败后就不再重试
+        assert_eq!(RetryPolicy::new(0).delay_after(1), None);
+    }
+
+    #[test]
+    fn retry_delay_is_capped_by_max_delay() {
+        let policy = RetryPolicy::new(10).base_delay(Duration::from_secs(1)).multiplier(3.0).max_delay(Duration::from_secs(5));
+        assert_eq!(policy.delay_after(2), Some(Duration::from_secs(3)));
+        assert_eq!(policy.delay_after(3), Some(Duration::from_secs(5)));
+        assert_eq!(policy.delay_after(9), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn retry_jitter_stays_in_range_and_under_max_delay() {
+        let policy = RetryPolicy::new(3).base_delay(Duration::from_millis(100)).jitter(0.5);
+        for _ in 0..200 {
+            let delay = policy.delay_after(1).unwrap();
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(150), "{:?}", delay);
+        }
+        // 抖动后的等待时间同样不超过 max_delay, 抖动比例超过 1 按 1 计算
+        let policy = RetryPolicy::new(3).base_delay(Duration::from_millis(100)).max_delay(Duration::from_millis(100)).jitter(2.0);
+        for _ in 0..200 {
+            assert!(policy.delay_after(1).unwrap() <= Duration::from_millis(100));
+        }
+    }
+
+    // 一个工作线程正在运行 running, 后面排着 queued 个任务, 按 mode 关闭
+    fn shutdown_with_queue(mode: ShutdownMode) -> (Vec<TaskId>, ShutdownSummary) {
+        let scheduler = Scheduler::with_workers(1);
+        scheduler.start();
+        let running = scheduler.add_task(Priority::High, Box::new(SleepTask("running", 200))).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let mut ids = vec![running.id()];
+        for _ in 0..2 {
+            ids.push(scheduler.add_task(Priority::High, Box::new(SleepTask("queued", 1))).unwrap().id());
+        }
+        (ids, scheduler.shutdown(mode))
+    }
+
+    #[test]
+    fn drain_shutdown_runs_everything() {
+        let (ids, summary) = shutdown_with_queue(ShutdownMode::Drain);
+        assert_eq!(summary.completed, ids);
+        assert!(summary.dropped.is_empty() && summary.interrupted.is_empty());
+    }
+
+    #[test]
+    fn finish_running_shutdown_drops_the_queue() {
+        let (ids, summary) = shutdown_with_queue(ShutdownMode::FinishRunning);
+        assert_eq!(summary.completed, ids[..1]);
+        assert_eq!(summary.dropped, ids[1..]);
+        assert!(summary.interrupted.is_empty());
+    }
+
+    #[test]
+    fn immediate_shutdown_interrupts_running_tasks() {
+        let (ids, summary) = shutdown_with_queue(ShutdownMode::Immediate);
+        assert!(summary.completed.is_empty());
+        assert_eq!(summary.interrupted, ids[..1]);
+        assert_eq!(summary.dropped, ids[1..]);
+    }
+
+    #[test]
+    fn shutdown_rejects_new_tasks() {
+        let scheduler = Scheduler::new();
+        scheduler.shutdown(ShutdownMode::Drain);
+        assert!(matches!(scheduler.add_task(Priority::High, Box::new(SleepTask("late", 1))), Err(TaskError::ShutDown)));
+    }
+
+    #[test]
+    fn set_priority_reorders_queued_tasks() {
+        let scheduler = Scheduler::new();
+        let ids: Vec<TaskId> = [Priority::Low, Priority::Low, Priority::Medium]
+            .into_iter()
+            .map(|priority| scheduler.add_task(priority, Box::new(SleepTask("task", 1))).unwrap().id())
+            .collect();
+        scheduler.set_priority(ids[1], Priority::High).unwrap();
+        assert_eq!(scheduler.status(ids[1]).unwrap(), TaskStatus::Queued { effective: Priority::High });
+
+        let mut state = scheduler.shared.state.lock().unwrap();
+        let order: Vec<TaskId> = std::iter::from_fn(|| state.pop_ready().map(|queued| queued.id)).collect();
+        assert_eq!(order, vec![ids[1], ids[2], ids[0]]);
+    }
+
+    #[test]
+    fn set_priority_rejects_running_and_finished_tasks() {
+        let scheduler = Scheduler::with_workers(1);
+        scheduler.start();
+        let handle = scheduler.add_task(Priority::Low, Box::new(SleepTask("running", 100))).unwrap();
+        let id = handle.id();
+        thread::sleep(Duration::from_millis(30));
+        assert!(matches!(scheduler.set_priority(id, Priority::High), Err(TaskError::NotFound)));
+        handle.join().unwrap();
+        assert!(matches!(scheduler.set_priority(id, Priority::High), Err(TaskError::NotFound)));
+        assert!(matches!(scheduler.set_priority(TaskId(999), Priority::High), Err(TaskError::NotFound)));
+        scheduler.shutdown(ShutdownMode::Drain);
+    }
+}
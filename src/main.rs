@@ -1,134 +1,43 @@
-use std::{fmt, sync::{Arc, Mutex}, thread, time::Duration};
-
-const COLOR_REST: &str = "\x1b[0m";
-const COLOR_RED: &str = "\x1b[31m";
-const COLOR_GREEN: &str = "\x1b[32m";
-const COLOR_YELLOW: &str = "\x1b[33m";
-
-// 自定义错误类型
-// dervie 自动实现 trait ("接口")
-#[derive(Debug)]
-enum TaskError {
-    ExecutionError(String), // 运行错误
-    TimeOut,
-    NotFound,
-}
-
-// 为枚举类 TaskError 实现 fmt::Display
-impl fmt::Display for TaskError {
-    // &self: taskerror 不可变引用  &mut: 可变引用 <'_>: 生命周期为这个函数 f = formatter: 格式化工具
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            TaskError::ExecutionError(msg) => write!(f, "执行任务失败: {}", msg),
-            TaskError::TimeOut => write!(f, "任务超时"),
-            TaskError::NotFound => write!(f, "找不到任务")
-        }
-    }
-}
+use std::time::{Duration, SystemTime};
 
-// 自动实现 Priority 的 格式打印 {:?}, .clone(), 逻辑判断 == / !=
-#[derive(Debug, Clone, PartialEq)]
-enum Priority {
-    High,
-    Medium,
-    Low,
-}
-
-// 特性: 接口
-// send: 所有权可以转移 sync: 可以被多线程共享
-trait Executable: Send + Sync {
-    fn execute(&self) -> Result<(), TaskError>;
-    fn get_name(&self) -> String;
-}
+use task_flow_rs::{
+    AdmissionPolicy, Executable, Priority, Schedule, Scheduler, SimpleTask, SliceOutcome, TaskContext, TaskError, TaskHandle, TaskOptions,
+    COLOR_GREEN, COLOR_REST, COLOR_YELLOW,
+};
 
-struct SimpleTask {
-    name: String,
-    duration_secs: u64,
+// 数据同步任务, 运行结束后输出同步的记录数
+struct DataSyncTask {
+    inner: SimpleTask,
+    records: u64,
 }
 
-// 为 simpletask 实现 executable 特性, result 是枚举
-impl Executable for SimpleTask {
-    fn execute(&self) -> Result<(), TaskError> {
-        println!("正在运行任务: {}", self.name);
-        if self.duration_secs > 5 {
-            return Err(TaskError::ExecutionError("任务需要运行时间过长, 系统拒绝".to_string()));
-        }
-
-        thread::sleep(Duration::from_secs(self.duration_secs));
+impl Executable for DataSyncTask {
+    fn execute(&self, ctx: &TaskContext) -> Result<(), TaskError> {
+        self.inner.execute(ctx)?;
+        ctx.set_output(self.records);
         Ok(())
     }
 
     fn get_name(&self) -> String {
-        self.name.clone()
+        self.inner.get_name()
     }
-}
-
-// Arc: 可以多线程共享  Box: 一个指向堆分配内存的指针
-// Mutex: 互斥锁 Vec: 要求每个元素大小固定
-struct Scheduler {
-    tasks: Arc<Mutex<Vec<(Priority, Box<dyn Executable>)>>>,
-}
 
-impl Scheduler {
-    // 创建
-    fn new() -> Self {
-        Scheduler { 
-            tasks: Arc::new(Mutex::new(Vec::new())), 
+    fn run_slice(&self, ctx: &TaskContext, quantum: Duration) -> Result<SliceOutcome, TaskError> {
+        let outcome = self.inner.run_slice(ctx, quantum)?;
+        if outcome == SliceOutcome::Finished {
+            ctx.set_output(self.records);
         }
+        Ok(outcome)
     }
 
-    // 添加任务
-    fn add_task(&self, priority: Priority, task: Box<dyn Executable>) {
-        let mut tasks = self.tasks.lock().unwrap();
-        tasks.push((priority, task));
-        tasks.sort_by(|a, b| {
-            let priority_val = |p: &Priority| match p {
-                Priority::High => 0,
-                Priority::Medium => 1,
-                Priority::Low => 2,
-            };
-            // a.0 = a.Priority
-            priority_val(&b.0).cmp(&priority_val(&a.0))
-        });
-    } 
-
-    // 并发处理任务, 主线程(调度器) 其他线程(处理任务)
-    fn run_all(self) {
-        // 调度器中需要处理的任务
-        let task_arc = Arc::clone(&self.tasks);
-    
-        // 开启新线程来处理
-        let handle = thread::spawn(move || {
-            // 可变 tasks
-            let mut tasks = task_arc.lock().unwrap();
-            println!("--- 调度器开始工作, 待处理任务总数: {}", tasks.len());
-            println!();
-
-            // 使用迭代器处理
-            while let Some((priority, task)) = tasks.pop() {
-                println!("{}[{:?}]{} 准备运行: {}",  match priority {
-                   Priority::High => COLOR_RED, 
-                   Priority::Medium => COLOR_YELLOW, 
-                   Priority::Low => COLOR_GREEN, 
-                },  priority, COLOR_REST, task.get_name());
-
-                match task.execute() {
-                    Ok(_) => println!("{}Successfully Finished: {}{}", COLOR_GREEN,  COLOR_REST, task.get_name()),
-                    Err(e) => eprintln!("{}Error running :{} {} {}", COLOR_RED, COLOR_REST, task.get_name(), e),
-                }
-                println!();
-            }
-
-            println!("--- 所有任务执行完毕 ---");
-        });
-
-        handle.join().unwrap();
+    fn estimated_duration(&self) -> Option<Duration> {
+        self.inner.estimated_duration()
     }
 }
 
-// 随机生成任务 
-fn random_task(scheduler: &Scheduler) {
-    let task_name = vec![
+// 随机生成任务, 返回数据同步任务的句柄
+fn random_task(scheduler: &Scheduler) -> Vec<TaskHandle> {
+    let task_name = [
         "系统扫描", "数据同步", "邮件发送", "缓存清理", 
         "安全审计", "日志压缩", "前端构建", "AI 模型推理",
     ];
@@ -143,6 +52,7 @@ fn random_task(scheduler: &Scheduler) {
     let m = 10;
     println!("--- 开始随机生成 {} 个任务", m);
 
+    let mut sync_handles = Vec::new();
     for i in 0..m {
         seed = (seed * 1103515245 + 12345) & 0x7fffffff; // 简单的线性同余伪随机
 
@@ -161,25 +71,84 @@ fn random_task(scheduler: &Scheduler) {
         let limit = 10;
         let duration = (seed % limit) + 1;
 
-        let task = Box::new(SimpleTask {
-            name,
-            duration_secs: duration
-        });
+        let inner = SimpleTask::new(name.clone(), duration);
+        let task: Box<dyn Executable> = if task_name[name_idx] == "数据同步" {
+            Box::new(DataSyncTask { inner, records: seed % 1000 * 10 })
+        } else {
+            Box::new(inner)
+        };
+
+        let summary = format!("{} | 优先级: {:?} | 预估时间: {}s", name, priority, duration);
 
-        println!("{}已添加任务: {} {} | 优先级: {:?} | 预估时间: {}s", COLOR_YELLOW, COLOR_REST, task.get_name(),  priority, duration);
-        scheduler.add_task(priority, task);
+        // 模型推理可能卡住, 给它设置超时时间
+        let result = if task_name[name_idx] == "AI 模型推理" {
+            scheduler.add_task_with(priority, task, TaskOptions::new().timeout(Duration::from_secs(3)))
+        } else {
+            scheduler.add_task(priority, task)
+        };
+        match result {
+            Ok(handle) => {
+                println!("{}已添加任务 {}: {} {}", COLOR_YELLOW, handle.id(), COLOR_REST, summary);
+                if task_name[name_idx] == "数据同步" {
+                    sync_handles.push(handle);
+                }
+            }
+            Err(e) => eprintln!("添加任务失败: {} {}", summary, e),
+        }
     }
 
     println!();
+    sync_handles
+}
+
+// 注册每晚运行的维护任务 (UTC 时间), 打印下一次运行时间
+fn nightly_tasks(scheduler: &Scheduler) {
+    let jobs = [("缓存清理", "0 3 * * *"), ("日志压缩", "30 3 * * *")];
+    for (name, expr) in jobs {
+        let registered = Schedule::cron(expr).and_then(|schedule| {
+            scheduler.add_recurring(Priority::Low, schedule, move || Box::new(SimpleTask::new(format!("每晚 - {}", name), 2)))
+        });
+        match registered.and_then(|id| scheduler.next_fire_time(id)) {
+            Ok(Some(next)) => {
+                let wait = next.duration_since(SystemTime::now()).unwrap_or_default();
+                println!("{}已注册周期任务: {} {}[{}] 距离下次运行 {}h{}m", COLOR_YELLOW, COLOR_REST, name, expr, wait.as_secs() / 3600, wait.as_secs() / 60 % 60);
+            }
+            Ok(None) => println!("周期任务 {} 不会触发: {}", name, expr),
+            Err(e) => eprintln!("注册周期任务失败: {} {}", name, e),
+        }
+    }
+    println!();
 }
 
 fn main() {
     println!("--- TaskFlow 开始 ---");
     println!();
 
-    let scheduler = Scheduler::new();
+    // 第一个命令行参数为工作线程数, 第二个为 RR 时间片秒数, 例如 cargo run -- 8 2
+    let args: Vec<String> = std::env::args().collect();
+    let mut scheduler = match args.get(1).and_then(|arg| arg.parse().ok()) {
+        Some(workers) => Scheduler::with_workers(workers),
+        None => Scheduler::new(),
+    };
+    match args.get(2).and_then(|arg| arg.parse().ok()) {
+        Some(secs) => scheduler = scheduler.round_robin(Duration::from_secs(secs)),
+        // 不分片运行时, 预估运行时间超过 5 秒的任务在提交时就被拒绝; RR 模式下长任务可以分片运行
+        None => scheduler = scheduler.admission(AdmissionPolicy::new().max_estimate(Duration::from_secs(5))),
+    }
 
-    random_task(&scheduler);
+    // 先启动工作线程, 任务在提交的同时就开始运行
+    scheduler.start();
+    nightly_tasks(&scheduler);
+    let sync_handles = random_task(&scheduler);
 
     scheduler.run_all();
+
+    // 通过任务句柄读取数据同步任务的输出
+    for handle in sync_handles {
+        let id = handle.id();
+        match handle.join_as::<u64>() {
+            Ok(records) => println!("{}数据同步 {} 完成, 同步记录数: {}{}", COLOR_GREEN, id, records, COLOR_REST),
+            Err(e) => println!("数据同步 {} 没有输出: {}", id, e),
+        }
+    }
 }
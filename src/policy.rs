@@ -0,0 +1,690 @@
+use std::{
+    cmp::{self, Reverse},
+    collections::{BinaryHeap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crate::{Priority, QueueOrder, TaskId, PRIORITY_LEVELS};
+
+// 就绪任务的调度信息, 调度策略根据它决定出队顺序
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub priority: Priority,         // 有效优先级 (包含老化的提升)
+    pub seq: u64,                   // 入队序号, 每次入队 (包括 RR 重新入队) 递增
+    pub requeued: bool,             // 是否是 RR 时间片用完后重新入队
+    pub enqueued_at: Instant,       // 进入就绪队列的时间
+    pub deadline: Option<Instant>,  // 截止时间
+    pub estimate: Option<Duration>, // 预估运行时间 (TaskOptions::estimate 或任务自己声明的)
+    pub ran_for: Duration,          // 已经运行的时间, RR 模式下累计各个时间片
+    pub tenant: String,             // 所属租户
+}
+
+// 调度策略: 保存就绪任务的编号并决定出队顺序
+// 调度器在同一把锁下调用这些方法, 实现不需要考虑并发
+pub trait SchedulingPolicy: Send {
+    // 任务进入就绪队列
+    fn push(&mut self, task: TaskInfo);
+
+    // 取出下一个要运行的任务
+    fn pop(&mut self) -> Option<TaskId>;
+
+    // 从队列中移除任务 (取消, 调整优先级等), 任务不在队列中时返回 false
+    fn remove(&mut self, id: TaskId) -> bool;
+
+    // 任务运行了一段时间 (运行结束或用完一个时间片) 后调用, 需要统计运行时间的策略可以实现
+    fn on_ran(&mut self, _task: &TaskInfo, _ran: Duration) {}
+
+    // 任务成功运行结束后调用, task.ran_for 是总的运行时间
+    fn on_finished(&mut self, _task: &TaskInfo) {}
+
+    // 任务有了最终结果 (成功, 失败, 取消, 跳过等) 后调用, 之后不会再入队, 可以清理为它保存的状态
+    fn on_settled(&mut self, _id: TaskId) {}
+
+    // 任务这次运行的时间片, None 表示使用调度器的 round_robin 设置
+    fn time_slice(&self, _task: &TaskInfo) -> Option<Duration> {
+        None
+    }
+
+    // 是否按截止时间排序, 提交任务时的截止时间检查据此估算排在前面的任务
+    fn orders_by_deadline(&self) -> bool {
+        false
+    }
+}
+
+// 按 key 从小到大出队的队列, 内置策略共用
+// remove 只把任务标记为失效, 堆里的旧条目在出队时跳过, 调整优先级和老化时的 remove + push 都是 O(log n)
+struct KeyedQueue<K: Ord> {
+    heap: BinaryHeap<Reverse<(K, TaskId, u64)>>,
+    live: HashMap<TaskId, u64>, // 队列中的任务 -> 最近一次入队的版本, 版本不同的条目已经失效
+    next_version: u64,
+}
+
+impl<K: Ord> Default for KeyedQueue<K> {
+    fn default() -> Self {
+        KeyedQueue { heap: BinaryHeap::new(), live: HashMap::new(), next_version: 0 }
+    }
+}
+
+impl<K: Ord> KeyedQueue<K> {
+    fn push(&mut self, key: K, id: TaskId) {
+        let version = self.next_version;
+        self.next_version += 1;
+        self.live.insert(id, version);
+        self.heap.push(Reverse((key, id, version)));
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        while let Some(Reverse((_, id, version))) = self.heap.pop() {
+            if self.live.get(&id) == Some(&version) {
+                self.live.remove(&id);
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        if self.live.remove(&id).is_none() {
+            return false;
+        }
+        // 失效条目超过一半时清理一次, 均摊下来仍是 O(log n)
+        if self.heap.len() > 2 * self.live.len() + 64 {
+            let live = &self.live;
+            self.heap.retain(|Reverse((_, id, version))| live.get(id) == Some(version));
+        }
+        true
+    }
+
+    fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = (K, TaskId)> + '_ {
+        let live = &mut self.live;
+        self.heap.drain().filter_map(move |Reverse((key, id, version))| {
+            (live.get(&id) == Some(&version)).then(|| {
+                live.remove(&id);
+                (key, id)
+            })
+        })
+    }
+}
+
+// 严格优先级 (默认): 先比较优先级, 同优先级时按 QueueOrder 比较入队序号
+#[derive(Default)]
+pub struct PriorityPolicy {
+    order: QueueOrder,
+    queue: KeyedQueue<(Reverse<Priority>, bool, i128)>,
+}
+
+impl PriorityPolicy {
+    pub fn new(order: QueueOrder) -> Self {
+        PriorityPolicy { order, queue: KeyedQueue::default() }
+    }
+}
+
+impl SchedulingPolicy for PriorityPolicy {
+    fn push(&mut self, task: TaskInfo) {
+        let seq = task.seq as i128;
+        let tie = match self.order {
+            // seq 小的先出队
+            QueueOrder::Fifo => (false, seq),
+            // 新提交的任务 seq 大的先出队; RR 重新入队的任务排在它们后面, 彼此之间仍按先后轮转
+            QueueOrder::Lifo if task.requeued => (true, seq),
+            QueueOrder::Lifo => (false, -seq),
+        };
+        self.queue.push((Reverse(task.priority), tie.0, tie.1), task.id);
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        self.queue.pop()
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        self.queue.remove(id)
+    }
+}
+
+// 先进先出: 完全按入队顺序, 不看优先级
+#[derive(Default)]
+pub struct FifoPolicy {
+    queue: KeyedQueue<u64>,
+}
+
+impl FifoPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SchedulingPolicy for FifoPolicy {
+    fn push(&mut self, task: TaskInfo) {
+        self.queue.push(task.seq, task.id);
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        self.queue.pop()
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        self.queue.remove(id)
+    }
+}
+
+// 最早截止时间优先 (EDF): 有截止时间的任务按截止时间出队, 排在没有截止时间的任务前面
+// 截止时间相同或都没有时按优先级和入队顺序
+#[derive(Default)]
+pub struct EarliestDeadlineFirst {
+    queue: KeyedQueue<(bool, Option<Instant>, Reverse<Priority>, u64)>,
+}
+
+impl EarliestDeadlineFirst {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SchedulingPolicy for EarliestDeadlineFirst {
+    fn push(&mut self, task: TaskInfo) {
+        let key = (task.deadline.is_none(), task.deadline, Reverse(task.priority), task.seq);
+        self.queue.push(key, task.id);
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        self.queue.pop()
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        self.queue.remove(id)
+    }
+
+    fn orders_by_deadline(&self) -> bool {
+        true
+    }
+}
+
+// 学习到的运行时间中, 最近一次运行所占的比重
+const LEARNING_RATE: f64 = 0.5;
+
+// 短作业优先 (SJF): 剩余运行时间短的先出队, 没有预估的任务排在最后
+// 任务没有预估运行时间时, 使用同名任务过去运行时间的指数移动平均
+// 配合 round_robin 使用时, 每个时间片结束后按剩余时间重新排队, 相当于 SRTF
+#[derive(Default)]
+pub struct ShortestJobFirst {
+    queue: KeyedQueue<(bool, Duration, Reverse<Priority>, u64)>,
+    learned: HashMap<String, Duration>,
+}
+
+impl ShortestJobFirst {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 根据同名任务过去的运行时间学习到的预估值
+    pub fn learned_estimate(&self, name: &str) -> Option<Duration> {
+        self.learned.get(name).copied()
+    }
+}
+
+impl SchedulingPolicy for ShortestJobFirst {
+    fn push(&mut self, task: TaskInfo) {
+        let estimate = task.estimate.or_else(|| self.learned_estimate(&task.name));
+        let remaining = estimate.map(|estimate| estimate.saturating_sub(task.ran_for));
+        let key = (remaining.is_none(), remaining.unwrap_or_default(), Reverse(task.priority), task.seq);
+        self.queue.push(key, task.id);
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        self.queue.pop()
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        self.queue.remove(id)
+    }
+
+    fn on_finished(&mut self, task: &TaskInfo) {
+        let average = match self.learned.get(&task.name) {
+            Some(average) => average.mul_f64(1.0 - LEARNING_RATE) + task.ran_for.mul_f64(LEARNING_RATE),
+            None => task.ran_for,
+        };
+        self.learned.insert(task.name.clone(), average);
+    }
+}
+
+// 没有预估运行时间的任务按这个代价计算
+const DEFAULT_COST: Duration = Duration::from_secs(1);
+
+// 加权公平队列 (WFQ): 每个优先级是一个流, 按权重分配运行机会, 低优先级的任务不会被饿死
+// 入队时计算虚拟完成时间 = max(当前虚拟时间, 同一个流上一个任务的完成时间) + 代价 / 权重, 完成时间小的先出队
+#[derive(Default)]
+pub struct WeightedFairQueuing {
+    weights: HashMap<Priority, u32>,
+    virtual_time: u128,
+    last_finish: HashMap<Priority, u128>, // 每个流最后一个任务的虚拟完成时间
+    queue: KeyedQueue<(u128, u64)>,
+    tags: HashMap<TaskId, u128>,          // 排队任务的虚拟开始时间, 出队时推进虚拟时间
+}
+
+impl WeightedFairQueuing {
+    // 默认权重为优先级数值 + 1, 例如 High 的任务获得的运行机会约是 Low 的 3 倍
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 设置某个优先级的权重, 至少为 1
+    pub fn weight(mut self, priority: Priority, weight: u32) -> Self {
+        self.weights.insert(priority, weight.max(1));
+        self
+    }
+
+    fn weight_of(&self, priority: Priority) -> u32 {
+        self.weights.get(&priority).copied().unwrap_or(priority.value() as u32 + 1)
+    }
+}
+
+impl SchedulingPolicy for WeightedFairQueuing {
+    fn push(&mut self, task: TaskInfo) {
+        let cost = task.estimate.unwrap_or(DEFAULT_COST).as_nanos();
+        let start = cmp::max(self.virtual_time, self.last_finish.get(&task.priority).copied().unwrap_or(0));
+        let finish = start + cost / self.weight_of(task.priority) as u128;
+        self.last_finish.insert(task.priority, finish);
+        self.tags.insert(task.id, start);
+        self.queue.push((finish, task.seq), task.id);
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        let id = self.queue.pop()?;
+        if let Some(start) = self.tags.remove(&id) {
+            self.virtual_time = self.virtual_time.max(start);
+        }
+        Some(id)
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        self.tags.remove(&id);
+        self.queue.remove(id)
+    }
+}
+
+// 按租户公平分配 (FairShare): 每个租户获得的运行时间与权重成正比, 一个租户大量提交任务不会占满调度器
+// 每个租户记录虚拟运行时间 = 累计运行时间 / 权重, 出队时选择虚拟运行时间最小的租户, 租户内部按优先级和入队顺序
+// 出队时先按预估运行时间记账, 运行后按实际运行时间修正, 多个工作线程同时取任务时也会轮到其他租户
+#[derive(Default)]
+pub struct FairShare {
+    weights: HashMap<String, u32>,
+    tenants: HashMap<String, TenantQueue>,
+    queued: HashMap<TaskId, (String, Duration)>, // 排队任务所属的租户和预估运行时间
+    charged: HashMap<TaskId, Duration>,          // 已出队的任务预先记账的时间
+}
+
+#[derive(Default)]
+struct TenantQueue {
+    queue: KeyedQueue<(Reverse<Priority>, u64)>,
+    vruntime: i128, // 累计运行时间 (纳秒) / 权重
+}
+
+impl FairShare {
+    // 默认每个租户的权重都是 1
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 设置租户的权重, 至少为 1
+    pub fn weight(mut self, tenant: impl Into<String>, weight: u32) -> Self {
+        self.weights.insert(tenant.into(), weight.max(1));
+        self
+    }
+
+    // 给租户记账, time 可以为负 (实际运行时间比预先记账的少)
+    fn charge(&mut self, tenant: &str, time: i128) {
+        let weight = self.weights.get(tenant).copied().unwrap_or(1) as i128;
+        self.tenants.entry(tenant.to_string()).or_default().vruntime += time / weight;
+    }
+}
+
+impl SchedulingPolicy for FairShare {
+    fn push(&mut self, task: TaskInfo) {
+        let active_min = self.tenants.values().filter(|tenant| tenant.queue.len() > 0).map(|tenant| tenant.vruntime).min();
+        let tenant = self.tenants.entry(task.tenant.clone()).or_default();
+        // 租户空闲期间不积累额度, 重新有任务时至少从当前最小的虚拟运行时间开始
+        if tenant.queue.len() == 0
+            && let Some(active_min) = active_min
+        {
+            tenant.vruntime = tenant.vruntime.max(active_min);
+        }
+        tenant.queue.push((Reverse(task.priority), task.seq), task.id);
+        let cost = task.estimate.map(|estimate| estimate.saturating_sub(task.ran_for)).unwrap_or(DEFAULT_COST);
+        self.queued.insert(task.id, (task.tenant, cost));
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        let tenant = self.tenants.iter()
+            .filter(|(_, tenant)| tenant.queue.len() > 0)
+            .min_by(|(a_name, a), (b_name, b)| (a.vruntime, a_name).cmp(&(b.vruntime, b_name)))
+            .map(|(name, _)| name.clone())?;
+        let id = self.tenants.get_mut(&tenant)?.queue.pop()?;
+        let (_, cost) = self.queued.remove(&id)?;
+        self.charge(&tenant, cost.as_nanos() as i128);
+        self.charged.insert(id, cost);
+        Some(id)
+    }
+
+    fn remove(&mut self, id: TaskId) -> bool {
+        match self.queued.remove(&id) {
+            Some((tenant, _)) => self.tenants.get_mut(&tenant).is_some_and(|tenant| tenant.queue.remove(id)),
+            None => false,
+        }
+    }
+
+    fn on_ran(&mut self, task: &TaskInfo, ran: Duration) {
+        let charged = self.charged.remove(&task.id).unwrap_or_default();
+        self.charge(&task.tenant, ran.as_nanos() as i128 - charged.as_nanos() as i128);
+    }
+}
+
+// 多级反馈队列 (MLFQ): 按 Priority 的命名级别从 Critical 到 Background 分成 5 级, 新提交的任务从最高级开始
+// 在同一级累计用完 demote_after 个时间片的任务降一级, 级别越低时间片越长; 很快结束的交互型任务一直留在高级别
+// 每隔 boost_every 所有排队任务回到最高级, 长任务不会被饿死. 同一级内按入队顺序轮转, 提交时的优先级不参与排序
+// 时间片由策略决定, 不需要开启 round_robin, 但任务需要实现 run_slice 才能被分片
+pub struct MultilevelFeedback {
+    base_quantum: Duration, // 最高级的时间片, 每降一级翻倍
+    demote_after: u32,
+    boost_every: Duration,
+    next_boost: Instant,
+    levels: Vec<KeyedQueue<u64>>,            // 下标 0 是最高级
+    tasks: HashMap<TaskId, FeedbackLevel>,   // 还没有最终结果的任务所在的级别
+}
+
+#[derive(Default)]
+struct FeedbackLevel {
+    level: usize,
+    used: Duration, // 在当前级别累计的运行时间
+}
+
+impl Default for MultilevelFeedback {
+    fn default() -> Self {
+        MultilevelFeedback {
+            base_quantum: Duration::from_millis(100),
+            demote_after: 2,
+            boost_every: Duration::from_secs(5),
+            next_boost: Instant::now() + Duration::from_secs(5),
+            levels: PRIORITY_LEVELS.iter().map(|_| KeyedQueue::default()).collect(),
+            tasks: HashMap::new(),
+        }
+    }
+}
+
+impl MultilevelFeedback {
+    // 默认最高级时间片 100ms, 用完 2 个时间片降级, 每 5 秒提升一次
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 最高级的时间片, 至少 1ms
+    pub fn base_quantum(mut self, quantum: Duration) -> Self {
+        self.base_quantum = quantum.max(Duration::from_millis(1));
+        self
+    }
+
+    // 在同一级累计用完多少个时间片后降级, 至少为 1
+    pub fn demote_after(mut self, slices: u32) -> Self {
+        self.demote_after = slices.max(1);
+        self
+    }
+
+    // 所有任务回到最高级的间隔
+    pub fn boost_every(mut self, interval: Duration) -> Self {
+        self.boost_every = interval;
+        self.next_boost = Instant::now() + interval;
+        self
+    }
+
+    fn quantum_of(&self, level: usize) -> Duration {
+        self.base_quantum * (1 << level)
+    }
+
+    // 到了提升时间时把所有排队任务放回最高级
+    // 运行中的任务的记录也一并清掉, 它们重新入队时同样从最高级开始
+    fn boost_if_due(&mut self) {
+        let now = Instant::now();
+        if now < self.next_boost {
+            return;
+        }
+        self.next_boost = now + self.boost_every;
+        let queued: Vec<(u64, TaskId)> = self.levels.iter_mut().flat_map(|level| level.drain().collect::<Vec<_>>()).collect();
+        self.tasks.clear();
+        for (seq, id) in queued {
+            self.tasks.insert(id, FeedbackLevel::default());
+            self.levels[0].push(seq, id);
+        }
+    }
+}
+
+impl SchedulingPolicy for MultilevelFeedback {
+    fn push(&mut self, task: TaskInfo) {
+        // 第一次入队的任务从最高级开始; 时间片用完, 重试, 调整优先级或老化后重新入队的任务留在原来的级别
+        let level = self.tasks.entry(task.id).or_default().level;
+        self.levels[level].push(task.seq, task.id);
+    }
+
+    fn pop(&mut self) -> Option<TaskId> {
+        self.boost_if_due();
+        self.levels.iter_mut().find_map(|level| level.pop())
+    }
+
+    // 只从队列中移除, 级别保留到 on_settled, 重新入队时不会回到最高级
+    fn remove(&mut self, id: TaskId) -> bool {
+        match self.tasks.get(&id) {
+            Some(state) => self.levels[state.level].remove(id),
+            None => false,
+        }
+    }
+
+    fn on_ran(&mut self, task: &TaskInfo, ran: Duration) {
+        let allotment = self.quantum_of(self.tasks.get(&task.id).map_or(0, |state| state.level)) * self.demote_after;
+        let bottom = self.levels.len() - 1;
+        if let Some(state) = self.tasks.get_mut(&task.id) {
+            state.used += ran;
+            if state.used >= allotment && state.level < bottom {
+                state.level += 1;
+                state.used = Duration::ZERO;
+            }
+        }
+    }
+
+    fn on_settled(&mut self, id: TaskId) {
+        self.tasks.remove(&id);
+    }
+
+    fn time_slice(&self, task: &TaskInfo) -> Option<Duration> {
+        Some(self.quantum_of(self.tasks.get(&task.id).map_or(0, |state| state.level)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(id: u64, seq: u64, requeued: bool) -> TaskInfo {
+        TaskInfo {
+            id: TaskId(id),
+            name: format!("task-{}", id),
+            priority: Priority::Medium,
+            seq,
+            requeued,
+            enqueued_at: Instant::now(),
+            deadline: None,
+            estimate: None,
+            ran_for: Duration::ZERO,
+            tenant: crate::DEFAULT_TENANT.to_string(),
+        }
+    }
+
+    fn with_priority(mut task: TaskInfo, priority: Priority) -> TaskInfo {
+        task.priority = priority;
+        task
+    }
+
+    fn pop_all(policy: &mut dyn SchedulingPolicy) -> Vec<u64> {
+        std::iter::from_fn(|| policy.pop()).map(|id| id.0).collect()
+    }
+
+    #[test]
+    fn priority_policy_fifo_and_lifo_within_a_level() {
+        for (order, expected) in [(QueueOrder::Fifo, vec![3, 0, 1, 2]), (QueueOrder::Lifo, vec![3, 2, 1, 0])] {
+            let mut policy = PriorityPolicy::new(order);
+            for id in 0..3 {
+                policy.push(info(id, id, false));
+            }
+            policy.push(with_priority(info(3, 3, false), Priority::High));
+            assert_eq!(pop_all(&mut policy), expected);
+        }
+    }
+
+    #[test]
+    fn lifo_puts_requeued_tasks_behind_new_ones() {
+        let mut policy = PriorityPolicy::new(QueueOrder::Lifo);
+        policy.push(info(0, 0, true));
+        policy.push(info(1, 1, true));
+        policy.push(info(2, 2, false));
+        policy.push(info(3, 3, false));
+        // 新任务后进先出, RR 重新入队的任务之间按先后轮转
+        assert_eq!(pop_all(&mut policy), vec![3, 2, 0, 1]);
+    }
+
+    #[test]
+    fn wfq_shares_turns_by_weight() {
+        let mut policy = WeightedFairQueuing::new().weight(Priority::High, 3).weight(Priority::Low, 1);
+        for id in 0..6 {
+            policy.push(with_priority(info(id, id, false), Priority::High));
+        }
+        for id in 6..12 {
+            policy.push(with_priority(info(id, id, false), Priority::Low));
+        }
+        // High 的权重是 Low 的 3 倍, 每运行 3 个 High 的任务轮到一个 Low 的任务
+        assert_eq!(pop_all(&mut policy), vec![0, 1, 2, 6, 3, 4, 5, 7, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn wfq_late_flow_starts_from_current_virtual_time() {
+        let mut policy = WeightedFairQueuing::new().weight(Priority::High, 3).weight(Priority::Low, 1);
+        for id in 0..6 {
+            policy.push(with_priority(info(id, id, false), Priority::High));
+        }
+        assert_eq!(policy.pop(), Some(TaskId(0)));
+        assert_eq!(policy.pop(), Some(TaskId(1)));
+        assert_eq!(policy.pop(), Some(TaskId(2)));
+        // 后到的流不会因为之前空闲而连续获得运行机会
+        policy.push(with_priority(info(6, 6, false), Priority::Low));
+        assert_eq!(pop_all(&mut policy), vec![3, 4, 6, 5]);
+    }
+
+    fn with_tenant(mut task: TaskInfo, tenant: &str) -> TaskInfo {
+        task.tenant = tenant.to_string();
+        task
+    }
+
+    #[test]
+    fn fair_share_splits_turns_by_tenant_weight() {
+        let mut policy = FairShare::new().weight("a", 2);
+        for id in 0..6 {
+            policy.push(with_tenant(info(id, id, false), "a"));
+            policy.push(with_tenant(info(id + 10, id + 10, false), "b"));
+        }
+        let tenants: Vec<bool> = (0..9).map(|_| policy.pop().unwrap().0 < 10).collect();
+        // a 的权重是 b 的 2 倍, 运行次数也是 2 倍
+        assert_eq!(tenants, vec![true, false, true, true, false, true, true, false, true]);
+    }
+
+    #[test]
+    fn fair_share_idle_tenant_does_not_bank_credit() {
+        let mut policy = FairShare::new();
+        for id in 0..4 {
+            policy.push(with_tenant(info(id, id, false), "a"));
+            policy.push(with_tenant(info(id + 10, id + 10, false), "b"));
+        }
+        for _ in 0..4 {
+            policy.pop();
+        }
+        // c 从当前最小的虚拟运行时间开始, 和 a, b 轮流运行
+        policy.push(with_tenant(info(20, 20, false), "c"));
+        assert_eq!(policy.pop(), Some(TaskId(2)));
+        assert_eq!(policy.pop(), Some(TaskId(12)));
+        assert_eq!(policy.pop(), Some(TaskId(20)));
+    }
+
+    #[test]
+    fn keyed_queue_skips_removed_entries() {
+        let mut queue = KeyedQueue::default();
+        for id in 0..1000 {
+            queue.push(id, TaskId(id));
+        }
+        for id in 0..999 {
+            assert!(queue.remove(TaskId(id)));
+        }
+        assert!(!queue.remove(TaskId(0)));
+        // 失效条目被清理, 堆的大小不随移除次数增长
+        assert!(queue.heap.len() <= 2 * queue.len() + 64);
+        // 移除后重新入队的任务按新的 key 排序
+        queue.push(2000, TaskId(999));
+        queue.push(5, TaskId(5));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some(TaskId(5)));
+        assert_eq!(queue.pop(), Some(TaskId(999)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    fn mlfq() -> MultilevelFeedback {
+        MultilevelFeedback::new().base_quantum(Duration::from_millis(10)).demote_after(1).boost_every(Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn mlfq_demotes_after_using_its_slices() {
+        let mut policy = mlfq();
+        policy.push(info(0, 0, false));
+        assert_eq!(policy.pop(), Some(TaskId(0)));
+        assert_eq!(policy.time_slice(&info(0, 0, false)), Some(Duration::from_millis(10)));
+        policy.on_ran(&info(0, 0, false), Duration::from_millis(10));
+        policy.push(info(0, 1, true));
+        assert_eq!(policy.time_slice(&info(0, 1, true)), Some(Duration::from_millis(20)));
+
+        // 新任务在最高级, 先于降级的任务出队
+        policy.push(info(1, 2, false));
+        assert_eq!(policy.pop(), Some(TaskId(1)));
+        assert_eq!(policy.pop(), Some(TaskId(0)));
+    }
+
+    #[test]
+    fn mlfq_keeps_level_across_remove_and_push() {
+        let mut policy = mlfq();
+        policy.push(info(0, 0, false));
+        policy.pop();
+        policy.on_ran(&info(0, 0, false), Duration::from_millis(10));
+        policy.push(info(0, 1, true));
+
+        // 调整优先级和老化都是先 remove 再 push
+        assert!(policy.remove(TaskId(0)));
+        policy.push(info(0, 2, false));
+        assert_eq!(policy.time_slice(&info(0, 2, false)), Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn mlfq_forgets_settled_tasks() {
+        let mut policy = mlfq();
+        for id in 0..3 {
+            policy.push(info(id, id, false));
+        }
+        // 运行中失败, 排队时被取消, 成功结束
+        let failed = policy.pop().unwrap();
+        assert!(policy.remove(TaskId(1)));
+        policy.on_settled(failed);
+        policy.on_settled(TaskId(1));
+        let finished = policy.pop().unwrap();
+        policy.on_finished(&info(finished.0, 2, false));
+        policy.on_settled(finished);
+        assert!(policy.tasks.is_empty());
+        assert_eq!(policy.pop(), None);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::TaskError;
+
+// 周期任务的触发规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Cron(CronExpr),  // cron 表达式, 按 UTC 时间计算
+    Every(Duration), // 固定间隔, 从注册时开始计算
+}
+
+impl Schedule {
+    // 解析 5 段 (分 时 日 月 周) 或 6 段 (秒 分 时 日 月 周) 的 cron 表达式
+    // 也支持 @yearly @monthly @weekly @daily @hourly
+    pub fn cron(expr: &str) -> Result<Self, TaskError> {
+        CronExpr::parse(expr).map(Schedule::Cron)
+    }
+
+    // 固定间隔, 间隔不能为 0
+    pub fn every(interval: Duration) -> Result<Self, TaskError> {
+        if interval.is_zero() {
+            return Err(TaskError::InvalidSchedule("间隔不能为 0".to_string()));
+        }
+        Ok(Schedule::Every(interval))
+    }
+
+    // after 之后 (不含) 的下一次触发时间, cron 表达式永远不会触发时 (例如 2 月 30 日) 返回 None
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        match self {
+            Schedule::Cron(expr) => expr.next_after(after),
+            Schedule::Every(interval) => Some(after + *interval),
+        }
+    }
+}
+
+// 上一次运行还没结束时, 又到了触发时间的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlapPolicy {
+    #[default]
+    Skip,       // 跳过这次触发
+    Queue,      // 记下这次触发, 上一次运行结束后再运行
+    Concurrent, // 直接运行, 多次运行可以同时进行
+}
+
+// 解析后的 cron 表达式, 每个字段用位图表示允许的取值
+#[derive(Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    source: String,
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,     // 1-31
+    months: u64,   // 1-12
+    weekdays: u64, // 0-6, 0 是周日
+    days_any: bool,     // 日字段以 * 或 ? 开头 (包括 */n)
+    weekdays_any: bool, // 周字段以 * 或 ? 开头 (包括 */n)
+}
+
+const MONTH_NAMES: [&str; 12] = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+// 日期和星期的组合最多 28 年重复一次, 超过这个范围还没找到说明永远不会触发
+const SEARCH_DAYS: u64 = 366 * 28;
+
+impl CronExpr {
+    fn parse(expr: &str) -> Result<Self, TaskError> {
+        let expanded = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let (second, rest) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            n => return Err(TaskError::InvalidSchedule(format!("cron 表达式需要 5 或 6 段, 实际为 {} 段: {}", n, expr))),
+        };
+        let invalid = |field: &str| TaskError::InvalidSchedule(format!("无法解析 cron 字段 \"{}\": {}", field, expr));
+
+        let parse = |field: &str, min: u32, max: u32, names: &[&str]| parse_field(field, min, max, names).ok_or_else(|| invalid(field));
+        let mut weekdays = parse(rest[4], 0, 7, &WEEKDAY_NAMES)?;
+        // 7 也表示周日
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(CronExpr {
+            source: expr.trim().to_string(),
+            seconds: parse(second, 0, 59, &[])?,
+            minutes: parse(rest[0], 0, 59, &[])?,
+            hours: parse(rest[1], 0, 23, &[])?,
+            days: parse(rest[2], 1, 31, &[])?,
+            months: parse(rest[3], 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            days_any: rest[2].starts_with(['*', '?']),
+            weekdays_any: rest[4].starts_with(['*', '?']),
+        })
+    }
+
+    fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let start = after.duration_since(UNIX_EPOCH).map(|d| d.as_secs() + 1).unwrap_or(0);
+        let first_day = start / 86400;
+        for day in first_day..first_day + SEARCH_DAYS {
+            if !self.matches_date(day) {
+                continue;
+            }
+            let from = if day == first_day { start % 86400 } else { 0 };
+            if let Some(secs) = self.first_time_of_day(from) {
+                return Some(UNIX_EPOCH + Duration::from_secs(day * 86400 + secs));
+            }
+        }
+        None
+    }
+
+    // 日和周都有限制时满足其一即可, 其中一个以 * 开头 (例如 */2) 时需要同时满足, 与 Vixie cron 一致
+    fn matches_date(&self, day: u64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        let weekday = (day + 4) % 7; // 1970-01-01 是周四
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        let day_ok = self.days & (1 << day_of_month) != 0;
+        let weekday_ok = self.weekdays & (1 << weekday) != 0;
+        match (self.days_any, self.weekdays_any) {
+            (false, false) => day_ok || weekday_ok,
+            _ => day_ok && weekday_ok,
+        }
+    }
+
+    // 当天 from 秒 (含) 之后第一个满足时, 分, 秒字段的时刻
+    fn first_time_of_day(&self, from: u64) -> Option<u64> {
+        let (from_hour, from_minute, from_second) = (from / 3600, from / 60 % 60, from % 60);
+        for hour in from_hour..24 {
+            if self.hours & (1 << hour) == 0 {
+                continue;
+            }
+            let minute_start = if hour == from_hour { from_minute } else { 0 };
+            for minute in minute_start..60 {
+                if self.minutes & (1 << minute) == 0 {
+                    continue;
+                }
+                let second_start = if hour == from_hour && minute == from_minute { from_second } else { 0 };
+                if let Some(second) = (second_start..60).find(|second| self.seconds & (1 << second) != 0) {
+                    return Some(hour * 3600 + minute * 60 + second);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Debug for CronExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CronExpr({:?})", self.source)
+    }
+}
+
+// 解析单个字段: *, ?, 数字, 名称, a-b, */n, a-b/n 以及用逗号分隔的组合
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+    let value = |text: &str| -> Option<u32> {
+        let upper = text.to_ascii_uppercase();
+        let number = match names.iter().position(|name| *name == upper) {
+            Some(index) => index as u32 + if min == 1 { 1 } else { 0 },
+            None => text.parse().ok()?,
+        };
+        (min..=max).contains(&number).then_some(number)
+    };
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>().ok().filter(|step| *step > 0)?)),
+            None => (part, None),
+        };
+        let (start, end) = match range {
+            "*" | "?" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // a/n 表示从 a 开始到最大值
+                None if step.is_some() => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return None;
+        }
+        for number in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << number;
+        }
+    }
+    Some(bits)
+}
+
+// 距 1970-01-01 的天数转换成 UTC 的 (年, 月, 日)
+fn civil_from_days(days: u64) -> (i64, u32, u32) {
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // UTC 时间转换成 SystemTime, civil_from_days 的逆运算
+    fn utc(year: i64, month: u32, day: u32, hour: u64, minute: u64, second: u64) -> SystemTime {
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let mp = (month as i64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = (era * 146097 + doe - 719468) as u64;
+        UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second)
+    }
+
+    fn next(expr: &str, after: SystemTime) -> Option<SystemTime> {
+        Schedule::cron(expr).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(parse_field("*/15", 0, 59, &[]), Some(1 << 0 | 1 << 15 | 1 << 30 | 1 << 45));
+        assert_eq!(parse_field("1-3,10", 0, 59, &[]), Some(0b1110 | 1 << 10));
+        assert_eq!(parse_field("10/20", 0, 59, &[]), Some(1 << 10 | 1 << 30 | 1 << 50));
+        assert_eq!(parse_field("mon-fri", 0, 7, &WEEKDAY_NAMES), Some(0b111110));
+        assert_eq!(parse_field("JAN,DEC", 1, 12, &MONTH_NAMES), Some(1 << 1 | 1 << 12));
+        assert_eq!(parse_field("60", 0, 59, &[]), None);
+        assert_eq!(parse_field("5-1", 0, 59, &[]), None);
+        assert_eq!(parse_field("*/0", 0, 59, &[]), None);
+        assert_eq!(parse_field("abc", 0, 59, &[]), None);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(matches!(Schedule::cron("* * *"), Err(TaskError::InvalidSchedule(_))));
+        assert!(matches!(Schedule::cron("0 0 32 * *"), Err(TaskError::InvalidSchedule(_))));
+        assert!(matches!(Schedule::every(Duration::ZERO), Err(TaskError::InvalidSchedule(_))));
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19783), (2024, 3, 1));
+        assert_eq!(civil_from_days(10957), (2000, 1, 1));
+    }
+
+    #[test]
+    fn daily_fires_at_next_midnight() {
+        assert_eq!(next("@daily", utc(2024, 1, 1, 10, 0, 0)), Some(utc(2024, 1, 2, 0, 0, 0)));
+        // 正好在触发时间上时取下一次
+        assert_eq!(next("@daily", utc(2024, 1, 2, 0, 0, 0)), Some(utc(2024, 1, 3, 0, 0, 0)));
+        assert_eq!(next("30 3 * * *", utc(2024, 1, 1, 3, 29, 59)), Some(utc(2024, 1, 1, 3, 30, 0)));
+    }
+
+    #[test]
+    fn supports_seconds_field() {
+        assert_eq!(next("*/10 * * * * *", utc(2024, 1, 1, 0, 0, 5)), Some(utc(2024, 1, 1, 0, 0, 10)));
+        assert_eq!(next("0 * * * * *", utc(2024, 1, 1, 23, 59, 30)), Some(utc(2024, 1, 2, 0, 0, 0)));
+    }
+
+    #[test]
+    fn seven_means_sunday() {
+        // 2024-01-03 是周三
+        let sunday = Some(utc(2024, 1, 7, 0, 0, 0));
+        assert_eq!(next("0 0 * * 7", utc(2024, 1, 3, 0, 0, 0)), sunday);
+        assert_eq!(next("0 0 * * 0", utc(2024, 1, 3, 0, 0, 0)), sunday);
+        assert_eq!(next("0 0 * * SUN", utc(2024, 1, 3, 0, 0, 0)), sunday);
+    }
+
+    #[test]
+    fn crosses_month_and_year_boundaries() {
+        // 2 月没有 31 日
+        assert_eq!(next("0 0 31 * *", utc(2024, 1, 31, 12, 0, 0)), Some(utc(2024, 3, 31, 0, 0, 0)));
+        assert_eq!(next("@yearly", utc(2024, 6, 1, 0, 0, 0)), Some(utc(2025, 1, 1, 0, 0, 0)));
+        assert_eq!(next("0 0 29 2 *", utc(2024, 3, 1, 0, 0, 0)), Some(utc(2028, 2, 29, 0, 0, 0)));
+        assert_eq!(next("59 23 31 12 *", utc(2024, 12, 31, 23, 58, 0)), Some(utc(2024, 12, 31, 23, 59, 0)));
+    }
+
+    #[test]
+    fn impossible_date_never_fires() {
+        assert_eq!(next("0 0 30 2 *", utc(2024, 1, 1, 0, 0, 0)), None);
+    }
+
+    #[test]
+    fn day_and_weekday_rules() {
+        // 日和周都有限制: 满足其一即可, 2024-01-05 是周五
+        assert_eq!(next("0 0 13 * FRI", utc(2024, 1, 1, 0, 0, 0)), Some(utc(2024, 1, 5, 0, 0, 0)));
+        assert_eq!(next("0 0 13 * FRI", utc(2024, 1, 12, 0, 0, 0)), Some(utc(2024, 1, 13, 0, 0, 0)));
+        // 日字段以 * 开头时需要同时满足: 奇数日并且是周五
+        assert_eq!(next("0 0 */2 * FRI", utc(2024, 1, 1, 0, 0, 0)), Some(utc(2024, 1, 5, 0, 0, 0)));
+        assert_eq!(next("0 0 */2 * FRI", utc(2024, 1, 5, 0, 0, 0)), Some(utc(2024, 1, 19, 0, 0, 0)));
+    }
+
+    #[test]
+    fn every_adds_interval() {
+        let start = utc(2024, 1, 1, 0, 0, 0);
+        let schedule = Schedule::every(Duration::from_secs(90)).unwrap();
+        assert_eq!(schedule.next_after(start), Some(utc(2024, 1, 1, 0, 1, 30)));
+    }
+}